@@ -1,61 +1,379 @@
-use crate::token::{Token, Value};
+use std::{cell::Cell, rc::Rc};
+
+use crate::token::{Span, Token, Value};
+
+/// The number of scopes between a variable's use and its declaration, as
+/// filled in by the resolver. Unresolved variables are assumed to be global.
+#[derive(Debug, Default, PartialEq)]
+pub struct Depth(Cell<Option<usize>>);
+
+impl Depth {
+    pub fn get(&self) -> Option<usize> {
+        self.0.get()
+    }
+
+    pub fn set(&self, depth: usize) {
+        self.0.set(Some(depth));
+    }
+}
 
 pub trait ExprVisitor<R> {
-    fn visit_expr(&self, expr: &Expr) -> R;
+    fn visit_expr(&mut self, expr: &Expr) -> R;
+}
+
+/// An expression, along with the span of source it was parsed from.
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+        depth: Depth,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
     Grouping(Box<Expr>),
+    /// `object[index]`, where `bracket` is the closing bracket.
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    List(Vec<Expr>),
     Literal(Value),
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    /// The keys and values of a map literal, in order.
+    Map(Vec<(Expr, Expr)>),
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Super {
+        keyword: Token,
+        method: Token,
+        depth: Depth,
+    },
+    This {
+        keyword: Token,
+        depth: Depth,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
+    Variable {
+        name: Token,
+        depth: Depth,
+    },
 }
 
 impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+    pub fn assign(name: Token, value: Expr) -> Self {
+        let span = name.span.to(value.span);
+        let kind = ExprKind::Assign {
+            name,
+            value: Box::new(value),
+            depth: Depth::default(),
+        };
+        Self::new(kind, span)
+    }
     pub fn binary(left: Expr, operator: Token, right: Expr) -> Self {
-        Self::Binary {
+        let span = left.span.to(right.span);
+        let kind = ExprKind::Binary {
             left: Box::new(left),
             operator,
             right: Box::new(right),
-        }
+        };
+        Self::new(kind, span)
+    }
+    pub fn call(callee: Expr, paren: Token, arguments: Vec<Expr>) -> Self {
+        let span = callee.span.to(paren.span);
+        let kind = ExprKind::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        };
+        Self::new(kind, span)
+    }
+    pub fn get(object: Expr, name: Token) -> Self {
+        let span = object.span.to(name.span);
+        let kind = ExprKind::Get {
+            object: Box::new(object),
+            name,
+        };
+        Self::new(kind, span)
+    }
+    /// `span` covers the parentheses as well as `expr`.
+    pub fn grouping(expr: Expr, span: Span) -> Self {
+        Self::new(ExprKind::Grouping(Box::new(expr)), span)
+    }
+    pub fn index(object: Expr, bracket: Token, index: Expr) -> Self {
+        let span = object.span.to(bracket.span);
+        let kind = ExprKind::Index {
+            object: Box::new(object),
+            bracket,
+            index: Box::new(index),
+        };
+        Self::new(kind, span)
+    }
+    /// `span` covers the brackets as well as the elements.
+    pub fn list(elements: Vec<Expr>, span: Span) -> Self {
+        Self::new(ExprKind::List(elements), span)
+    }
+    pub fn literal(value: impl Into<Value>, span: Span) -> Self {
+        Self::new(ExprKind::Literal(value.into()), span)
+    }
+    pub fn logical(left: Expr, operator: Token, right: Expr) -> Self {
+        let span = left.span.to(right.span);
+        let kind = ExprKind::Logical {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        };
+        Self::new(kind, span)
+    }
+    /// `span` covers the braces as well as the entries.
+    pub fn map(entries: Vec<(Expr, Expr)>, span: Span) -> Self {
+        Self::new(ExprKind::Map(entries), span)
+    }
+    pub fn set(object: Expr, name: Token, value: Expr) -> Self {
+        let span = object.span.to(value.span);
+        let kind = ExprKind::Set {
+            object: Box::new(object),
+            name,
+            value: Box::new(value),
+        };
+        Self::new(kind, span)
     }
-    pub fn grouping(expr: Expr) -> Self {
-        Self::Grouping(Box::new(expr))
+    pub fn set_index(object: Expr, bracket: Token, index: Expr, value: Expr) -> Self {
+        let span = object.span.to(value.span);
+        let kind = ExprKind::SetIndex {
+            object: Box::new(object),
+            bracket,
+            index: Box::new(index),
+            value: Box::new(value),
+        };
+        Self::new(kind, span)
     }
-    pub fn literal(value: impl Into<Value>) -> Self {
-        Self::Literal(value.into())
+    pub fn super_expr(keyword: Token, method: Token) -> Self {
+        let span = keyword.span.to(method.span);
+        let kind = ExprKind::Super {
+            keyword,
+            method,
+            depth: Depth::default(),
+        };
+        Self::new(kind, span)
+    }
+    pub fn this(keyword: Token) -> Self {
+        let span = keyword.span;
+        let kind = ExprKind::This {
+            keyword,
+            depth: Depth::default(),
+        };
+        Self::new(kind, span)
     }
     pub fn unary(operator: Token, right: Expr) -> Self {
-        Self::Unary {
+        let span = operator.span.to(right.span);
+        let kind = ExprKind::Unary {
             operator,
             right: Box::new(right),
+        };
+        Self::new(kind, span)
+    }
+    pub fn variable(name: Token) -> Self {
+        let span = name.span;
+        let kind = ExprKind::Variable {
+            name,
+            depth: Depth::default(),
+        };
+        Self::new(kind, span)
+    }
+
+    /// The expression that a binary or logical operator, call, property or
+    /// index applies to, if this is one of those.
+    pub fn left(&self) -> Option<&Expr> {
+        match &self.kind {
+            ExprKind::Binary { left, .. } | ExprKind::Logical { left, .. } => Some(left),
+            ExprKind::Call { callee, .. } => Some(callee),
+            ExprKind::Get { object, .. } | ExprKind::Index { object, .. } => Some(object),
+            _ => None,
+        }
+    }
+
+    /// Split a chain such as `a + b - c` or `a.b(c)[d]`, which the parser
+    /// nests to the left, into its first operand and then each link in the
+    /// order they apply. Chains have no length limit, so walking them this way
+    /// rather than recursing through [`left`](Self::left) keeps long ones from
+    /// overflowing the native stack.
+    pub fn chain(&self) -> (&Expr, Vec<&Expr>) {
+        let mut links = vec![];
+        let mut first = self;
+        while let Some(left) = first.left() {
+            links.push(first);
+            first = left;
+        }
+        links.reverse();
+        (first, links)
+    }
+
+    /// Take out the expression a link applies to, leaving `nil` behind.
+    fn take_left(&mut self) -> Option<Expr> {
+        let left = match &mut self.kind {
+            ExprKind::Binary { left, .. } | ExprKind::Logical { left, .. } => left,
+            ExprKind::Call { callee, .. } => callee,
+            ExprKind::Get { object, .. } | ExprKind::Index { object, .. } => object,
+            _ => return None,
+        };
+        let placeholder = Expr::new(ExprKind::Literal(Value::None), left.span);
+        Some(std::mem::replace(left, placeholder))
+    }
+}
+
+// Dropping a long chain would recurse once per link, so take each link's
+// left operand out first and drop the chain one link at a time.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut left = self.take_left();
+        while let Some(mut expr) = left {
+            left = expr.take_left();
         }
     }
 }
 
 pub trait StmtVisitor<R> {
-    fn visit_stmt(&self, stmt: &Stmt) -> R;
+    fn visit_stmt(&mut self, stmt: &Stmt) -> R;
 }
 
+/// A statement, along with the span of source it was parsed from.
 #[derive(Debug, PartialEq)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
+    Block(Vec<Stmt>),
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Rc<FunctionDecl>>,
+    },
     Expression(Expr),
+    Function(Rc<FunctionDecl>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
     Print(Expr),
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Expr>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
 }
 
 impl Stmt {
-    pub fn expression(expression: Expr) -> Self {
-        Self::Expression(expression)
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+    pub fn block(statements: Vec<Stmt>, span: Span) -> Self {
+        Self::new(StmtKind::Block(statements), span)
+    }
+    pub fn class(
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<FunctionDecl>,
+        span: Span,
+    ) -> Self {
+        let kind = StmtKind::Class {
+            name,
+            superclass,
+            methods: methods.into_iter().map(Rc::new).collect(),
+        };
+        Self::new(kind, span)
+    }
+    pub fn expression(expression: Expr, span: Span) -> Self {
+        Self::new(StmtKind::Expression(expression), span)
     }
-    pub fn print(expression: Expr) -> Self {
-        Self::Print(expression)
+    pub fn function(declaration: FunctionDecl, span: Span) -> Self {
+        Self::new(StmtKind::Function(Rc::new(declaration)), span)
     }
+    pub fn if_stmt(
+        condition: Expr,
+        then_branch: Stmt,
+        else_branch: Option<Stmt>,
+        span: Span,
+    ) -> Self {
+        let kind = StmtKind::If {
+            condition,
+            then_branch: Box::new(then_branch),
+            else_branch: else_branch.map(Box::new),
+        };
+        Self::new(kind, span)
+    }
+    pub fn print(expression: Expr, span: Span) -> Self {
+        Self::new(StmtKind::Print(expression), span)
+    }
+    pub fn return_stmt(keyword: Token, value: Option<Expr>, span: Span) -> Self {
+        Self::new(StmtKind::Return { keyword, value }, span)
+    }
+    pub fn var(name: Token, initializer: Option<Expr>, span: Span) -> Self {
+        Self::new(StmtKind::Var { name, initializer }, span)
+    }
+    pub fn while_stmt(condition: Expr, body: Stmt, span: Span) -> Self {
+        let kind = StmtKind::While {
+            condition,
+            body: Box::new(body),
+        };
+        Self::new(kind, span)
+    }
+}
+
+/// A function declaration. Shared between the AST and every `LoxFunction`
+/// created from it, so that closures don't need to copy the function body.
+#[derive(Debug, PartialEq)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
 }
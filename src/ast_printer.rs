@@ -3,16 +3,21 @@ use crate::ast::{self, *};
 #[derive(Default, Debug)]
 pub(crate) struct AstPrinter;
 
+#[allow(dead_code)]
 impl AstPrinter {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn print(&self, expr: &Expr) -> String {
+    pub fn print(&mut self, expr: &Expr) -> String {
         self.visit_expr(expr)
     }
 
-    fn parenthesize(&self, name: &str, exprs: &[&Expr]) -> String {
+    pub fn print_stmt(&mut self, stmt: &Stmt) -> String {
+        self.visit_stmt(stmt)
+    }
+
+    fn parenthesize(&mut self, name: &str, exprs: &[&Expr]) -> String {
         let mut result = String::new();
         result.push('(');
         result.push_str(name);
@@ -23,21 +28,172 @@ impl AstPrinter {
         result.push(')');
         result
     }
+
+    /// Like `parenthesize`, for parts that have already been printed.
+    fn parenthesize_parts(name: &str, parts: impl IntoIterator<Item = String>) -> String {
+        let mut result = format!("({name}");
+        for part in parts {
+            result.push(' ');
+            result.push_str(&part);
+        }
+        result.push(')');
+        result
+    }
+
+    /// The name of one link of a chain, and the parts that follow what it
+    /// applies to.
+    fn link(&mut self, link: &Expr) -> (String, Vec<String>) {
+        match &link.kind {
+            ExprKind::Binary {
+                operator, right, ..
+            }
+            | ExprKind::Logical {
+                operator, right, ..
+            } => (operator.lexeme.clone(), vec![self.visit_expr(right)]),
+            ExprKind::Call { arguments, .. } => (
+                "call".to_string(),
+                arguments
+                    .iter()
+                    .map(|argument| self.visit_expr(argument))
+                    .collect(),
+            ),
+            ExprKind::Get { name, .. } => (format!(". {}", name.lexeme), vec![]),
+            ExprKind::Index { index, .. } => ("[]".to_string(), vec![self.visit_expr(index)]),
+            _ => (String::new(), vec![]),
+        }
+    }
+
+    fn function(&mut self, keyword: &str, function: &FunctionDecl) -> String {
+        let params: Vec<_> = function
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        let mut parts = vec![format!("({})", params.join(" "))];
+        parts.extend(function.body.iter().map(|stmt| self.visit_stmt(stmt)));
+        Self::parenthesize_parts(&format!("{keyword} {}", function.name.lexeme), parts)
+    }
 }
 
 impl ast::ExprVisitor<String> for AstPrinter {
-    fn visit_expr(&self, expr: &Expr) -> String {
-        match expr {
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => self.parenthesize(&operator.lexeme, &[left.as_ref(), right.as_ref()]),
-            Expr::Grouping(expr) => self.parenthesize("group", &[expr.as_ref()]),
-            Expr::Literal(value) => value.to_string(),
-            Expr::Unary { operator, right } => {
+    fn visit_expr(&mut self, expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Assign { name, value, .. } => {
+                self.parenthesize(&format!("= {}", name.lexeme), &[value.as_ref()])
+            }
+            ExprKind::Binary { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Get { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Logical { .. } => {
+                // Open each link's parentheses, print the first operand, then
+                // close them again along with the rest of each link.
+                let (first, links) = expr.chain();
+                let links: Vec<_> = links.into_iter().map(|link| self.link(link)).collect();
+                let mut result = String::new();
+                for (name, _) in links.iter().rev() {
+                    result.push('(');
+                    result.push_str(name);
+                    result.push(' ');
+                }
+                result.push_str(&self.visit_expr(first));
+                for (_, parts) in links {
+                    for part in parts {
+                        result.push(' ');
+                        result.push_str(&part);
+                    }
+                    result.push(')');
+                }
+                result
+            }
+            ExprKind::Grouping(expr) => self.parenthesize("group", &[expr.as_ref()]),
+            ExprKind::List(elements) => {
+                let elements: Vec<_> = elements.iter().collect();
+                self.parenthesize("list", &elements)
+            }
+            ExprKind::Literal(value) => value.repr(),
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => self.parenthesize(
+                &format!("= .{}", name.lexeme),
+                &[object.as_ref(), value.as_ref()],
+            ),
+            ExprKind::Map(entries) => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect();
+                self.parenthesize("map", &entries)
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => self.parenthesize("[]=", &[object.as_ref(), index.as_ref(), value.as_ref()]),
+            ExprKind::Super { method, .. } => format!("super.{}", method.lexeme),
+            ExprKind::This { .. } => "this".to_string(),
+            ExprKind::Unary { operator, right } => {
                 self.parenthesize(&operator.lexeme, &[right.as_ref()])
             }
+            ExprKind::Variable { name, .. } => name.lexeme.clone(),
+        }
+    }
+}
+
+impl ast::StmtVisitor<String> for AstPrinter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> String {
+        match &stmt.kind {
+            StmtKind::Block(statements) => {
+                let parts: Vec<_> = statements
+                    .iter()
+                    .map(|stmt| self.visit_stmt(stmt))
+                    .collect();
+                Self::parenthesize_parts("block", parts)
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let mut header = format!("class {}", name.lexeme);
+                if let Some(superclass) = superclass {
+                    header.push_str(&format!(" < {}", self.visit_expr(superclass)));
+                }
+                let methods: Vec<_> = methods
+                    .iter()
+                    .map(|method| self.function("method", method))
+                    .collect();
+                Self::parenthesize_parts(&header, methods)
+            }
+            StmtKind::Expression(expr) => self.parenthesize(";", &[expr]),
+            StmtKind::Function(function) => self.function("fun", function),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let mut parts = vec![self.visit_expr(condition), self.visit_stmt(then_branch)];
+                parts.extend(else_branch.as_ref().map(|stmt| self.visit_stmt(stmt)));
+                Self::parenthesize_parts("if", parts)
+            }
+            StmtKind::Print(expr) => self.parenthesize("print", &[expr]),
+            StmtKind::Return { value, .. } => {
+                let value: Vec<_> = value.iter().collect();
+                self.parenthesize("return", &value)
+            }
+            StmtKind::Var { name, initializer } => match initializer {
+                Some(initializer) => {
+                    self.parenthesize(&format!("var {} =", name.lexeme), &[initializer])
+                }
+                None => format!("(var {})", name.lexeme),
+            },
+            StmtKind::While { condition, body } => Self::parenthesize_parts(
+                "while",
+                [self.visit_expr(condition), self.visit_stmt(body)],
+            ),
         }
     }
 }
@@ -47,20 +203,56 @@ mod tests {
     use crate::{
         ast::{Expr, ExprVisitor},
         ast_printer::AstPrinter,
-        token::{Token, Value},
+        parser::parse,
+        scanner::scan_tokens,
+        token::{Span, Token, Value},
         token_type::TokenType,
     };
 
     #[test]
     fn test_example() {
-        let e = Expr::Binary {
-            left: Box::new(Expr::Unary {
-                operator: Token::new(TokenType::Minus, "-".into(), Value::None, 1),
-                right: Box::new(Expr::Literal(123_f64.into())),
-            }),
-            operator: Token::new(TokenType::Star, "*".into(), Value::None, 1),
-            right: Box::new(Expr::Grouping(Box::new(Expr::Literal((45.67).into())))),
+        let token = |token_type, lexeme: &str| {
+            Token::new(token_type, lexeme.into(), Value::None, Span::default())
         };
+        let e = Expr::binary(
+            Expr::unary(
+                token(TokenType::Minus, "-"),
+                Expr::literal(123_f64, Span::default()),
+            ),
+            token(TokenType::Star, "*"),
+            Expr::grouping(Expr::literal(45.67, Span::default()), Span::default()),
+        );
         assert_eq!("(* (- 123) (group 45.67))", AstPrinter {}.visit_expr(&e));
     }
+
+    #[test]
+    fn test_statements() {
+        let source = "class A < B { f(a, b) { return a; } }\nvar x;\nwhile (x) if (x) print 1; else { x = nil; }";
+        let (tokens, _) = scan_tokens(source);
+        let (statements, errors) = parse(tokens);
+        assert!(errors.is_empty());
+        let printed: Vec<_> = statements
+            .iter()
+            .map(|stmt| AstPrinter.print_stmt(stmt))
+            .collect();
+        assert_eq!(
+            printed,
+            [
+                "(class A < B (method f (a b) (return a)))",
+                "(var x)",
+                "(while x (if x (print 1) (block (; (= x nil)))))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chains() {
+        let (tokens, _) = scan_tokens("print a.b(1, 2)[c]() + d * e or f;");
+        let (statements, errors) = parse(tokens);
+        assert!(errors.is_empty());
+        assert_eq!(
+            AstPrinter.print_stmt(&statements[0]),
+            "(print (or (+ (call ([] (call (. b a) 1 2) c)) (* d e)) f))"
+        );
+    }
 }
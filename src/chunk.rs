@@ -0,0 +1,230 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::token::Span;
+
+/// A single bytecode instruction. Operands follow the opcode in the chunk:
+/// constant indexes are two bytes (big-endian), as are jump offsets; local,
+/// upvalue, argument, element and entry counts are one byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    BuildList,
+    ExtendList,
+    BuildMap,
+    ExtendMap,
+    GetIndex,
+    SetIndex,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Invoke,
+    SuperInvoke,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 46] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::BuildList,
+        OpCode::ExtendList,
+        OpCode::BuildMap,
+        OpCode::ExtendMap,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+    ];
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// A value that can be stored in a chunk's constant pool.
+#[derive(Debug, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    /// Shared so that the VM can look up globals and properties by name
+    /// without copying it.
+    String(Rc<str>),
+    Function(Rc<Function>),
+}
+
+/// How a constant is found again in the pool. Numbers are compared by their
+/// bits, which keeps `0` and `-0` apart.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl std::fmt::Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Number(number) => write!(f, "{number}"),
+            Constant::String(string) => f.write_str(string),
+            Constant::Function(function) => write!(f, "{function}"),
+        }
+    }
+}
+
+/// A sequence of bytecode, with its constant pool and the source span of
+/// every byte.
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Constant>,
+    /// The index of each number and string in `constants`.
+    indexes: HashMap<ConstantKey, usize>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    /// Add `constant` to the pool, returning its index. Identical strings
+    /// and numbers share a slot.
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let key = match &constant {
+            Constant::Number(number) => Some(ConstantKey::Number(number.to_bits())),
+            Constant::String(string) => Some(ConstantKey::String(string.clone())),
+            Constant::Function(_) => None,
+        };
+        if let Some(&index) = key.as_ref().and_then(|key| self.indexes.get(key)) {
+            return index;
+        }
+        let index = self.constants.len();
+        self.constants.push(constant);
+        if let Some(key) = key {
+            self.indexes.insert(key, index);
+        }
+        index
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+}
+
+/// A compiled function: its bytecode plus what's needed to call it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            f.write_str("<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_round_trip() {
+        for op in OpCode::ALL {
+            assert_eq!(OpCode::try_from(op as u8), Ok(op));
+        }
+        assert_eq!(OpCode::try_from(OpCode::ALL.len() as u8), Err(46));
+    }
+
+    #[test]
+    fn test_constants_are_shared() {
+        let mut chunk = Chunk::default();
+        let a = chunk.add_constant(Constant::String("a".into()));
+        let one = chunk.add_constant(Constant::Number(1.0));
+        assert_eq!(chunk.add_constant(Constant::String("a".into())), a);
+        assert_eq!(chunk.add_constant(Constant::Number(1.0)), one);
+        assert_eq!(chunk.constants.len(), 2);
+
+        let zero = chunk.add_constant(Constant::Number(0.0));
+        assert_ne!(chunk.add_constant(Constant::Number(-0.0)), zero);
+    }
+}
@@ -0,0 +1,150 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    callable::{LoxCallable, LoxFunction},
+    diagnostic::ErrorCode,
+    interpreter::{Interpreter, RuntimeError},
+    token::{Token, Value},
+};
+
+/// A class, shared by both backends. Methods are `Value::Function`s when
+/// created by the tree-walking interpreter, and `Value::Closure`s when
+/// created by the bytecode VM.
+#[derive(Debug)]
+pub struct LoxClass {
+    pub(crate) name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: RefCell<HashMap<String, Value>>,
+}
+
+impl LoxClass {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Value>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            superclass,
+            methods: RefCell::new(methods),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Look up a method on this class, falling back to its superclasses.
+    pub fn find_method(&self, name: &str) -> Option<Value> {
+        self.methods.borrow().get(name).cloned().or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
+    }
+
+    /// Look up a method created by the tree-walking interpreter.
+    pub(crate) fn find_function(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.find_method(name)? {
+            Value::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn add_method(&self, name: impl Into<String>, method: Value) {
+        self.methods.borrow_mut().insert(name.into(), method);
+    }
+
+    /// Copy all of `superclass`'s methods into this class, so that lookups
+    /// never need to walk the superclass chain. Used by the bytecode VM.
+    pub(crate) fn inherit(&self, superclass: &LoxClass) {
+        let methods = superclass.methods.borrow().clone();
+        self.methods.borrow_mut().extend(methods);
+    }
+}
+
+// Calling a class creates an instance, which needs a reference to the class
+// itself, so this is implemented on the `Rc` rather than on `LoxClass`.
+impl LoxCallable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        self.find_function("init")
+            .map_or(0, |initializer| initializer.arity())
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Rc::new(LoxInstance::new(self.clone()));
+        if let Some(initializer) = self.find_function("init") {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+/// An instance of a [`LoxClass`], holding its own fields.
+pub struct LoxInstance {
+    pub(crate) class: Rc<LoxClass>,
+    fields: RefCell<HashMap<String, Value>>,
+}
+
+impl LoxInstance {
+    pub(crate) fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: Default::default(),
+        }
+    }
+
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+
+    /// Look up a property: fields shadow methods, and methods are bound to
+    /// `instance` as they are accessed.
+    pub(crate) fn get(instance: &Rc<LoxInstance>, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = instance.field(&name.lexeme) {
+            return Ok(value);
+        }
+        if let Some(method) = instance.class.find_function(&name.lexeme) {
+            return Ok(Value::Function(Rc::new(method.bind(instance.clone()))));
+        }
+        Err(RuntimeError::new(
+            ErrorCode::UndefinedProperty,
+            name,
+            format!("Undefined property '{}'.", name.lexeme),
+        ))
+    }
+
+    pub fn field(&self, name: &str) -> Option<Value> {
+        self.fields.borrow().get(name).cloned()
+    }
+
+    pub fn set(&self, name: impl Into<String>, value: Value) {
+        self.fields.borrow_mut().insert(name.into(), value);
+    }
+}
+
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Instances compare by identity, and their fields may refer back to the
+// instance itself, so neither trait can be derived.
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
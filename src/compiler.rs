@@ -0,0 +1,784 @@
+use std::{iter, rc::Rc};
+
+use crate::{
+    ast::{Expr, ExprKind, FunctionDecl, Stmt, StmtKind},
+    chunk::{Chunk, Constant, Function, OpCode},
+    diagnostic::{Diagnostic, ErrorCode},
+    reporter::Reporter,
+    token::{Span, Token, Value},
+    token_type::TokenType,
+};
+
+/// The most locals, upvalues or call arguments a one-byte operand can address.
+const MAX_SLOTS: usize = u8::MAX as usize + 1;
+
+/// Compile a resolved program into the function for its top-level script.
+///
+/// The resolver has already reported scoping errors, so the only errors left
+/// to report here are programs too big for the bytecode format to express.
+pub fn compile(reporter: &mut dyn Reporter, statements: &[Stmt]) -> Option<Rc<Function>> {
+    let mut compiler = Compiler::new(reporter);
+    for statement in statements {
+        compiler.statement(statement);
+    }
+    let (function, _) = compiler.end_function();
+    (!compiler.had_error).then(|| Rc::new(function))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/// The compilation state of a single function. Functions nest, so the
+/// compiler keeps a stack of these.
+#[derive(Debug)]
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: &str) -> Self {
+        // Slot zero holds the callee, which methods can refer to as `this`.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            function: Function {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    reporter: &'a mut dyn Reporter,
+    states: Vec<FunctionState>,
+    /// The span of the most recent token, recorded for each byte written.
+    span: Span,
+    had_error: bool,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(reporter: &'a mut dyn Reporter) -> Self {
+        Self {
+            reporter,
+            states: vec![FunctionState::new(FunctionKind::Script, "")],
+            span: Span::default(),
+            had_error: false,
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass.as_ref(), methods),
+            StmtKind::Expression(expression) => {
+                self.expression(expression);
+                self.emit_op(OpCode::Pop);
+            }
+            StmtKind::Function(declaration) => {
+                self.span = declaration.name.span;
+                if self.current().scope_depth > 0 {
+                    // Declare the local first so the function can refer to
+                    // itself recursively.
+                    self.add_local(&declaration.name.lexeme);
+                    self.function(declaration, FunctionKind::Function);
+                } else {
+                    self.function(declaration, FunctionKind::Function);
+                    self.define_global(&declaration.name.lexeme);
+                }
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            StmtKind::Print(expression) => {
+                self.expression(expression);
+                self.emit_op(OpCode::Print);
+            }
+            StmtKind::Return { keyword, value } => {
+                self.span = keyword.span;
+                match value {
+                    Some(value) => {
+                        self.expression(value);
+                        self.emit_op(OpCode::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            StmtKind::Var { name, initializer } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit_op(OpCode::Nil),
+                }
+                self.span = name.span;
+                self.define_variable(&name.lexeme);
+            }
+            StmtKind::While { condition, body } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(body);
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Rc<FunctionDecl>]) {
+        self.span = name.span;
+        let name_constant = self.identifier_constant(&name.lexeme);
+        self.emit_constant_op(OpCode::Class, name_constant);
+        self.define_variable(&name.lexeme);
+
+        if let Some(superclass) = superclass {
+            // Methods of a subclass close over a local holding `super`.
+            self.begin_scope();
+            self.expression(superclass);
+            self.add_local("super");
+
+            self.named_variable(&name.lexeme, false);
+            self.emit_op(OpCode::Inherit);
+        }
+
+        self.named_variable(&name.lexeme, false);
+        for method in methods {
+            let kind = if method.name.lexeme == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+            let method_constant = self.identifier_constant(&method.name.lexeme);
+            self.emit_constant_op(OpCode::Method, method_constant);
+        }
+        self.emit_op(OpCode::Pop);
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    /// Compile `declaration` as a nested function and emit the instruction
+    /// that wraps it in a closure.
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) {
+        self.states
+            .push(FunctionState::new(kind, &declaration.name.lexeme));
+        self.begin_scope();
+
+        self.current().function.arity = declaration.params.len();
+        for param in &declaration.params {
+            self.add_local(&param.lexeme);
+        }
+        for statement in &declaration.body {
+            self.statement(statement);
+        }
+
+        let (function, upvalues) = self.end_function();
+        let constant = self.make_constant(Constant::Function(Rc::new(function)));
+        self.emit_constant_op(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    fn end_function(&mut self) -> (Function, Vec<Upvalue>) {
+        self.emit_return();
+        let mut state = self
+            .states
+            .pop()
+            .expect("The compiler always has a function state.");
+        state.function.upvalue_count = state.upvalues.len();
+        (state.function, state.upvalues)
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Assign { name, value, .. } => {
+                self.expression(value);
+                self.span = name.span;
+                self.named_variable(&name.lexeme, true);
+            }
+            ExprKind::Binary { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Get { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Logical { .. } => self.chain(expr),
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::List(elements) => self.build(
+                elements,
+                OpCode::BuildList,
+                OpCode::ExtendList,
+                expr.span,
+                Self::expression,
+            ),
+            ExprKind::Literal(value) => {
+                self.span = expr.span;
+                match value {
+                    Value::None => self.emit_op(OpCode::Nil),
+                    Value::Bool(true) => self.emit_op(OpCode::True),
+                    Value::Bool(false) => self.emit_op(OpCode::False),
+                    Value::Number(number) => {
+                        let constant = self.make_constant(Constant::Number(*number));
+                        self.emit_constant_op(OpCode::Constant, constant);
+                    }
+                    Value::String(string) => {
+                        let constant = self.make_constant(Constant::String(string.as_str().into()));
+                        self.emit_constant_op(OpCode::Constant, constant);
+                    }
+                    _ => self.error(ErrorCode::Internal, "Unexpected literal value."),
+                }
+            }
+            ExprKind::Map(entries) => self.build(
+                entries,
+                OpCode::BuildMap,
+                OpCode::ExtendMap,
+                expr.span,
+                |this, (key, value)| {
+                    this.expression(key);
+                    this.expression(value);
+                },
+            ),
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_constant_op(OpCode::SetProperty, constant);
+            }
+            ExprKind::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.span = bracket.span;
+                self.emit_op(OpCode::SetIndex);
+            }
+            ExprKind::Super {
+                keyword, method, ..
+            } => {
+                self.span = keyword.span;
+                self.named_variable("this", false);
+                self.named_variable("super", false);
+                let constant = self.identifier_constant(&method.lexeme);
+                self.emit_constant_op(OpCode::GetSuper, constant);
+            }
+            ExprKind::This { keyword, .. } => {
+                self.span = keyword.span;
+                self.named_variable("this", false);
+            }
+            ExprKind::Unary { operator, right } => {
+                self.expression(right);
+                self.span = operator.span;
+                match operator.token_type {
+                    TokenType::Bang => self.emit_op(OpCode::Not),
+                    TokenType::Minus => self.emit_op(OpCode::Negate),
+                    _ => self.error(ErrorCode::Internal, "Unexpected unary operator."),
+                }
+            }
+            ExprKind::Variable { name, .. } => {
+                self.span = name.span;
+                self.named_variable(&name.lexeme, false);
+            }
+        }
+    }
+
+    /// Compile a call. Calling a method directly on an object or on `super`
+    /// uses a single invoke instruction, which avoids creating a bound method.
+    /// Compile a chain of links, such as `a + b - c` or `a.b(c)[d]`, one link
+    /// at a time rather than recursing through each. A method call is a
+    /// single link that applies to the object the method is looked up on,
+    /// so that it compiles to one `Invoke`.
+    fn chain(&mut self, expr: &Expr) {
+        let mut links = vec![];
+        let mut left = Some(expr);
+        while let Some(link) = left.filter(|expr| expr.left().is_some()) {
+            links.push(link);
+            left = match &link.kind {
+                ExprKind::Call { callee, .. } => match &callee.kind {
+                    ExprKind::Get { object, .. } => Some(object),
+                    ExprKind::Super { .. } => None,
+                    _ => Some(callee),
+                },
+                _ => link.left(),
+            };
+        }
+
+        if let Some(first) = left {
+            self.expression(first);
+        }
+        for link in links.into_iter().rev() {
+            self.link(link);
+        }
+    }
+
+    /// Compile one link of a chain, after what it applies to.
+    fn link(&mut self, link: &Expr) {
+        match &link.kind {
+            ExprKind::Binary {
+                operator, right, ..
+            } => {
+                self.expression(right);
+                self.span = operator.span;
+                let op = match operator.token_type {
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    _ => return self.error(ErrorCode::Internal, "Unexpected binary operator."),
+                };
+                self.emit_op(op);
+            }
+            ExprKind::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.call(callee, paren, arguments),
+            ExprKind::Get { name, .. } => {
+                self.span = name.span;
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_constant_op(OpCode::GetProperty, constant);
+            }
+            ExprKind::Index { bracket, index, .. } => {
+                self.expression(index);
+                self.span = bracket.span;
+                self.emit_op(OpCode::GetIndex);
+            }
+            ExprKind::Logical {
+                operator, right, ..
+            } => {
+                self.span = operator.span;
+                let end_jump = if operator.token_type == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    end_jump
+                } else {
+                    self.emit_jump(OpCode::JumpIfFalse)
+                };
+                self.emit_op(OpCode::Pop);
+                self.expression(right);
+                self.patch_jump(end_jump);
+            }
+            _ => self.error(ErrorCode::Internal, "Unexpected link in a chain."),
+        }
+    }
+
+    /// Compile the items of a list or map literal, then `build` the value
+    /// from them. Counts are one byte, so after the first 255 items the rest
+    /// are added 255 at a time with `extend`.
+    fn build<T>(
+        &mut self,
+        items: &[T],
+        build: OpCode,
+        extend: OpCode,
+        span: Span,
+        mut item: impl FnMut(&mut Self, &T),
+    ) {
+        let (first, rest) = items.split_at(items.len().min(u8::MAX as usize));
+        let rest = rest.chunks(u8::MAX as usize).map(|chunk| (extend, chunk));
+        for (op, chunk) in iter::once((build, first)).chain(rest) {
+            for value in chunk {
+                item(self, value);
+            }
+            self.span = span;
+            self.emit_op(op);
+            self.emit_byte(chunk.len() as u8);
+        }
+    }
+
+    /// Compile a call, after its callee, or for a method call the object the
+    /// method is looked up on.
+    fn call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) {
+        let invoke = match &callee.kind {
+            ExprKind::Get { name, .. } => Some((OpCode::Invoke, name)),
+            ExprKind::Super {
+                keyword, method, ..
+            } => {
+                self.span = keyword.span;
+                self.named_variable("this", false);
+                Some((OpCode::SuperInvoke, method))
+            }
+            _ => None,
+        };
+
+        for argument in arguments {
+            self.expression(argument);
+        }
+        if arguments.len() >= MAX_SLOTS {
+            self.error(
+                ErrorCode::TooManyArguments,
+                "Can't have more than 255 arguments.",
+            );
+        }
+
+        self.span = paren.span;
+        match invoke {
+            Some((op, name)) => {
+                if op == OpCode::SuperInvoke {
+                    self.named_variable("super", false);
+                }
+                let constant = self.identifier_constant(&name.lexeme);
+                self.emit_constant_op(op, constant);
+            }
+            None => self.emit_op(OpCode::Call),
+        }
+        self.emit_byte(arguments.len() as u8);
+    }
+
+    /// Emit a read of the variable `name`, or a write of the value on top of
+    /// the stack if `assign` is set.
+    fn named_variable(&mut self, name: &str, assign: bool) {
+        let current = self.states.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(current, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name);
+            let op = if assign {
+                OpCode::SetGlobal
+            } else {
+                OpCode::GetGlobal
+            };
+            return self.emit_constant_op(op, constant);
+        };
+        self.emit_op(if assign { set } else { get });
+        self.emit_byte(operand);
+    }
+
+    fn resolve_local(&self, state: usize, name: &str) -> Option<u8> {
+        self.states[state]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, state: usize, name: &str) -> Option<u8> {
+        if state == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(state - 1, name) {
+            self.states[state - 1].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(state, slot, true));
+        }
+        let index = self.resolve_upvalue(state - 1, name)?;
+        Some(self.add_upvalue(state, index, false))
+    }
+
+    fn add_upvalue(&mut self, state: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &self.states[state].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+        if upvalues.len() >= MAX_SLOTS {
+            self.error(
+                ErrorCode::CompilerLimit,
+                "Too many closure variables in function.",
+            );
+            return 0;
+        }
+        self.states[state].upvalues.push(upvalue);
+        (self.states[state].upvalues.len() - 1) as u8
+    }
+
+    /// Bind the value on top of the stack to `name` in the current scope.
+    fn define_variable(&mut self, name: &str) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name);
+        } else {
+            self.define_global(name);
+        }
+    }
+
+    fn define_global(&mut self, name: &str) {
+        let constant = self.identifier_constant(name);
+        self.emit_constant_op(OpCode::DefineGlobal, constant);
+    }
+
+    fn add_local(&mut self, name: &str) {
+        if self.current().locals.len() >= MAX_SLOTS {
+            return self.error(
+                ErrorCode::CompilerLimit,
+                "Too many local variables in function.",
+            );
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.pop_if(|local| local.depth > depth) {
+            if local.is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    // -------------------------------------------------------------------------
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.states
+            .last_mut()
+            .expect("The compiler always has a function state.")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        for byte in value.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn emit_constant_op(&mut self, op: OpCode, constant: u16) {
+        self.emit_op(op);
+        self.emit_u16(constant);
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    /// Emit a jump with a placeholder offset, returning the offset's position
+    /// so that it can be patched once the target is known.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            return self.error(ErrorCode::CompilerLimit, "Too much code to jump over.");
+        };
+        self.chunk().code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let offset = self.chunk().code.len() - loop_start + 2;
+        let Ok(offset) = u16::try_from(offset) else {
+            return self.error(ErrorCode::CompilerLimit, "Loop body too large.");
+        };
+        self.emit_u16(offset);
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> u16 {
+        self.make_constant(Constant::String(name.into()))
+    }
+
+    fn make_constant(&mut self, constant: Constant) -> u16 {
+        let index = self.chunk().add_constant(constant);
+        u16::try_from(index).unwrap_or_else(|_| {
+            self.error(ErrorCode::CompilerLimit, "Too many constants in one chunk.");
+            0
+        })
+    }
+
+    fn error(&mut self, code: ErrorCode, message: &str) {
+        self.reporter
+            .error(Diagnostic::error(code, self.span, message));
+        self.had_error = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, reporter::Collector, resolver::resolve, scanner::scan_tokens};
+
+    fn compile_source(source: &str) -> Rc<Function> {
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        resolve(&mut reporter, &statements);
+        compile(&mut reporter, &statements).unwrap()
+    }
+
+    #[test]
+    fn test_expression_statement() {
+        let function = compile_source("1 + 2;");
+        let chunk = &function.chunk;
+        assert_eq!(
+            chunk.code,
+            [
+                OpCode::Constant as u8,
+                0,
+                0,
+                OpCode::Constant as u8,
+                0,
+                1,
+                OpCode::Add as u8,
+                OpCode::Pop as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+        assert_eq!(
+            chunk.constants,
+            [Constant::Number(1.0), Constant::Number(2.0)]
+        );
+        assert_eq!(chunk.spans[0], Span::new(0, 1, 1, 1));
+        assert_eq!(chunk.spans[3], Span::new(4, 1, 1, 5));
+        assert_eq!(chunk.spans[6], Span::new(2, 1, 1, 3));
+        assert!(chunk.spans.iter().all(|span| span.line == 1));
+    }
+
+    #[test]
+    fn test_locals_and_upvalues() {
+        let function = compile_source("{ var a = 1; fun f() { return a; } }");
+        let Constant::Function(inner) = &function.chunk.constants[1] else {
+            panic!("Expected a function constant");
+        };
+        assert_eq!(inner.name, "f");
+        assert_eq!(inner.upvalue_count, 1);
+        assert_eq!(
+            inner.chunk.code[..3],
+            [OpCode::GetUpvalue as u8, 0, OpCode::Return as u8]
+        );
+        // The captured local is closed rather than popped at the end of the block.
+        let code = &function.chunk.code;
+        assert_eq!(
+            code[code.len() - 4..],
+            [
+                OpCode::Pop as u8,
+                OpCode::CloseUpvalue as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_list() {
+        let elements = vec!["1"; 600].join(", ");
+        let function = compile_source(&format!("[{elements}];"));
+        let mut expected = vec![];
+        for (op, count) in [
+            (OpCode::BuildList, 255),
+            (OpCode::ExtendList, 255),
+            (OpCode::ExtendList, 90),
+        ] {
+            for _ in 0..count {
+                expected.extend([OpCode::Constant as u8, 0, 0]);
+            }
+            expected.extend([op as u8, count]);
+        }
+        expected.extend([OpCode::Pop as u8, OpCode::Nil as u8, OpCode::Return as u8]);
+        assert_eq!(function.chunk.code, expected);
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        let declarations: String = (0..300).map(|i| format!("var v{i};")).collect();
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(&format!("{{ {declarations} }}"));
+        let (statements, _) = parse(tokens);
+        assert!(compile(&mut reporter, &statements).is_none());
+        assert!(reporter.had_error());
+    }
+}
@@ -0,0 +1,348 @@
+//! Runs Lox files annotated in the style of the reference test suite, and
+//! checks what they print against the annotations:
+//!
+//! - `// expect: value` expects `value` as the next line of output.
+//! - `// expect runtime error: message` expects the program to stop with
+//!   `message`, reported on the annotation's line, and exit code 70.
+//! - `// Error at 'x': message` expects `[line N] Error at 'x': message`,
+//!   where N is the annotation's line, and exit code 65.
+//! - `// [line N] Error ...` expects an error reported on line N instead.
+//!   `// [java line N]` and `// [c line N]` only apply to the tree-walking
+//!   and bytecode backends respectively.
+//!
+//! Files containing `// nontest` are skipped.
+
+use std::{
+    fmt::{Display, Write as _},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Backend, Lox,
+    output::SharedBuffer,
+    reporter::{TextReporter, TextStyle},
+    with_large_stack,
+};
+
+/// What a test file expects running it to produce.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    /// Compile errors, as the short error format reports them.
+    pub errors: Vec<String>,
+    /// The message of the runtime error, and the line it is reported on.
+    pub runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    /// Read the annotations in `source` that apply to `backend`.
+    pub fn parse(source: &str, backend: Backend) -> Self {
+        let mut expectations = Self::default();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            if let Some(output) = annotation(line, "// expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = annotation(line, "// expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if let Some(error) = annotation(line, "// Error") {
+                expectations
+                    .errors
+                    .push(format!("[line {line_number}] Error{error}"));
+            } else if let Some(error) =
+                annotation(line, "// [").and_then(|rest| line_error(rest, backend))
+            {
+                expectations.errors.extend(error);
+            }
+        }
+        expectations
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if !self.errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+/// The rest of `line` after `marker`, if it has one. Like the reference
+/// test runner, this looks for the marker anywhere in the line rather than
+/// only in what looks like a comment, which may start inside a string.
+fn annotation<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let start = line.find(marker)?;
+    Some(&line[start + marker.len()..])
+}
+
+/// Parse the rest of a `[line N] Error ...` annotation after its `[`, giving
+/// `Some(None)` for one that only applies to the other backend.
+fn line_error(rest: &str, backend: Backend) -> Option<Option<String>> {
+    let (applies, rest) = match rest.split_once(' ') {
+        Some(("java", rest)) => (backend == Backend::TreeWalk, rest),
+        Some(("c", rest)) => (backend == Backend::Bytecode, rest),
+        _ => (true, rest),
+    };
+    let rest = rest.strip_prefix("line ")?;
+    let (line, error) = rest.split_once("] ")?;
+    if !error.starts_with("Error") || line.parse::<usize>().is_err() {
+        return None;
+    }
+    Some(applies.then(|| format!("[line {line}] {error}")))
+}
+
+/// What running a program produced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Run `source` on a fresh `backend`, reporting errors in the short format
+/// that the annotations use. Call this on a thread with a large stack, as
+/// [`run_dir`] does, for programs that recurse deeply.
+pub fn run(source: &str, backend: Backend) -> Outcome {
+    let stdout = SharedBuffer::new();
+    let stderr = SharedBuffer::new();
+    let mut lox = Lox::with_backend(backend);
+    lox.set_output(stdout.clone());
+    lox.set_reporter(TextReporter::with_writer(stderr.clone(), TextStyle::Short));
+    // `run` only fails to write output, which a buffer can't.
+    let _ = lox.run(source);
+    Outcome {
+        stdout: stdout.contents(),
+        stderr: stderr.contents(),
+        exit_code: lox.exit_code(),
+    }
+}
+
+/// Compare what a program produced with what it was expected to, describing
+/// each difference.
+pub fn check(expectations: &Expectations, outcome: &Outcome) -> Vec<String> {
+    let mut problems = vec![];
+
+    let output: Vec<_> = outcome.stdout.lines().collect();
+    if output != expectations.output {
+        problems.push(format!(
+            "Output differs:\n{}",
+            diff(&expectations.output, &output)
+        ));
+    }
+
+    let mut expected_errors = expectations.errors.clone();
+    if let Some((message, line)) = &expectations.runtime_error {
+        expected_errors.push(message.clone());
+        expected_errors.push(format!("[line {line}]"));
+    }
+    let errors: Vec<_> = outcome.stderr.lines().collect();
+    if errors != expected_errors {
+        problems.push(format!(
+            "Errors differ:\n{}",
+            diff(&expected_errors, &errors)
+        ));
+    }
+
+    if outcome.exit_code != expectations.exit_code() {
+        problems.push(format!(
+            "Expected exit code {} but got {}.",
+            expectations.exit_code(),
+            outcome.exit_code
+        ));
+    }
+    problems
+}
+
+/// A line diff, with `-` marking lines only in `expected` and `+` lines only
+/// in `actual`.
+fn diff(expected: &[String], actual: &[&str]) -> String {
+    // lengths[i][j] is the length of the longest common subsequence of
+    // expected[i..] and actual[j..].
+    let mut lengths = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut output = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            let _ = writeln!(output, "  {}", expected[i]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == actual.len()
+            || (i < expected.len() && lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            let _ = writeln!(output, "- {}", expected[i]);
+            i += 1;
+        } else {
+            let _ = writeln!(output, "+ {}", actual[j]);
+            j += 1;
+        }
+    }
+    output
+}
+
+/// A test file that didn't do what its annotations expect.
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub backend: Backend,
+    pub problems: Vec<String>,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "FAIL {} ({:?})", self.path.display(), self.backend)?;
+        for problem in &self.problems {
+            for line in problem.lines() {
+                writeln!(f, "    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The results of running every test in a directory.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub passed: usize,
+    pub skipped: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Summary {
+    pub fn succeeded(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for failure in &self.failures {
+            write!(f, "{failure}")?;
+        }
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.passed,
+            self.failures.len(),
+            self.skipped
+        )
+    }
+}
+
+/// Run every `.lox` file under `dir` on each of `backends`.
+pub fn run_dir(dir: &Path, backends: &[Backend]) -> io::Result<Summary> {
+    let mut paths = vec![];
+    find_tests(dir, &mut paths)?;
+    paths.sort();
+
+    let mut summary = Summary::default();
+    for path in paths {
+        let source = fs::read_to_string(&path)?;
+        if source.contains("// nontest") {
+            summary.skipped += 1;
+            continue;
+        }
+        for &backend in backends {
+            let expectations = Expectations::parse(&source, backend);
+            let outcome = with_large_stack(|| run(&source, backend))?;
+            let problems = check(&expectations, &outcome);
+            if problems.is_empty() {
+                summary.passed += 1;
+            } else {
+                summary.failures.push(Failure {
+                    path: path.clone(),
+                    backend,
+                    problems,
+                });
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn find_tests(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+print 1; // expect: 1
+var a = ; // Error at ';': Expect expression.
+// [line 7] Error at end: Expect '}' after block.
+// [java line 1] Error: Java only.
+// [c line 1] Error: C only.
+-nil; // expect runtime error: Operand must be a number.
+{
+print \"a // b\"; // expect: a // b
+print \"// c\"; // [c line 9] Error: Not a comment.
+";
+
+    #[test]
+    fn test_parse() {
+        let expectations = Expectations::parse(SOURCE, Backend::Bytecode);
+        assert_eq!(expectations.output, ["1", "a // b"]);
+        assert_eq!(
+            expectations.errors,
+            [
+                "[line 2] Error at ';': Expect expression.",
+                "[line 7] Error at end: Expect '}' after block.",
+                "[line 1] Error: C only.",
+                "[line 9] Error: Not a comment.",
+            ]
+        );
+        assert_eq!(
+            expectations.runtime_error,
+            Some(("Operand must be a number.".into(), 6))
+        );
+        assert_eq!(expectations.exit_code(), 65);
+    }
+
+    #[test]
+    fn test_check() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let source = "print 1; // expect: 1\nprint 2; // expect: 3\nprint -nil; // expect runtime error: Operand must be a number.";
+            let expectations = Expectations::parse(source, backend);
+            let outcome = run(source, backend);
+            assert_eq!(outcome.exit_code, 70);
+            assert_eq!(
+                check(&expectations, &outcome),
+                ["Output differs:\n  1\n- 3\n+ 2\n"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_errors() {
+        let source = "print 1;\nvar = 2; // Error at '=': Expect variable name.\nprint;";
+        let expectations = Expectations::parse(source, Backend::TreeWalk);
+        let outcome = run(source, Backend::TreeWalk);
+        assert_eq!(outcome.stdout, "");
+        assert_eq!(
+            check(&expectations, &outcome),
+            [
+                "Errors differ:\n  [line 2] Error at '=': Expect variable name.\n+ [line 3] Error at ';': Expect expression.\n"
+            ]
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    fmt::{Display, Write},
+    io::IsTerminal,
+};
+
+use crate::token::Span;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Whether diagnostics written to stderr should be coloured: only when it is
+/// a terminal, and the user hasn't opted out with `NO_COLOR`.
+pub fn stderr_supports_colour() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn colour(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// Identifies each kind of error. Codes are grouped by the phase that reports
+/// them: `E00xx` scanning, `E01xx` parsing, `E02xx` resolving, `E03xx`
+/// compiling to bytecode and `E04xx` running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A character that doesn't start any token.
+    UnexpectedCharacter,
+    /// A string with no closing quote.
+    UnterminatedString,
+    /// A token other than the one the grammar requires, or a missing
+    /// expression.
+    ExpectedToken,
+    /// An assignment to something other than a variable, field or element.
+    InvalidAssignmentTarget,
+    /// A function with more than 255 parameters, or a call with more than
+    /// 255 arguments.
+    TooManyArguments,
+    /// Statements or expressions nested too deeply to parse.
+    TooDeeplyNested,
+    /// A second declaration of a name in the same local scope.
+    DuplicateDeclaration,
+    /// A local variable read in its own initializer.
+    SelfReferentialInitializer,
+    /// A `return` outside a function, or with a value inside an initializer.
+    InvalidReturn,
+    /// `this` outside a class.
+    InvalidThis,
+    /// `super` outside a class, or in a class without a superclass.
+    InvalidSuper,
+    /// A class that inherits from itself.
+    CyclicInheritance,
+    /// A program too large for the bytecode format, such as one with too
+    /// many constants or locals.
+    CompilerLimit,
+    /// An operation on a value of the wrong type.
+    TypeMismatch,
+    /// A variable that was never defined.
+    UndefinedVariable,
+    /// A property that an instance has no field or method for.
+    UndefinedProperty,
+    /// A call to a value that isn't a function or class.
+    NotCallable,
+    /// A call with the wrong number of arguments.
+    ArityMismatch,
+    /// Calls, or the code running in them, nested too deeply.
+    StackOverflow,
+    /// A function written in Rust failed.
+    NativeError,
+    /// Reading the program's input or writing its output failed.
+    Io,
+    /// A list was indexed past its end.
+    IndexOutOfRange,
+    /// A map was indexed with a key it doesn't have.
+    MissingKey,
+    /// A state that a well-formed program can't reach, such as an unknown
+    /// opcode.
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0001",
+            ErrorCode::UnterminatedString => "E0002",
+            ErrorCode::ExpectedToken => "E0100",
+            ErrorCode::InvalidAssignmentTarget => "E0101",
+            ErrorCode::TooManyArguments => "E0102",
+            ErrorCode::TooDeeplyNested => "E0104",
+            ErrorCode::DuplicateDeclaration => "E0200",
+            ErrorCode::SelfReferentialInitializer => "E0201",
+            ErrorCode::InvalidReturn => "E0202",
+            ErrorCode::InvalidThis => "E0203",
+            ErrorCode::InvalidSuper => "E0204",
+            ErrorCode::CyclicInheritance => "E0205",
+            ErrorCode::CompilerLimit => "E0300",
+            ErrorCode::TypeMismatch => "E0400",
+            ErrorCode::UndefinedVariable => "E0401",
+            ErrorCode::UndefinedProperty => "E0402",
+            ErrorCode::NotCallable => "E0403",
+            ErrorCode::ArityMismatch => "E0404",
+            ErrorCode::StackOverflow => "E0405",
+            ErrorCode::NativeError => "E0406",
+            ErrorCode::Io => "E0407",
+            ErrorCode::IndexOutOfRange => "E0408",
+            ErrorCode::MissingKey => "E0409",
+            ErrorCode::Internal => "E0900",
+        }
+    }
+
+    /// A suggestion shown with every error of this kind, if there is a useful
+    /// one.
+    pub fn help(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::UnterminatedString => Some("add a closing '\"' to end the string"),
+            ErrorCode::InvalidAssignmentTarget => {
+                Some("only variables, fields and list elements can be assigned to")
+            }
+            ErrorCode::TooManyArguments => {
+                Some("pass a list or an instance to group related values")
+            }
+            ErrorCode::TooDeeplyNested => Some("move the inner parts into functions or variables"),
+            ErrorCode::SelfReferentialInitializer => {
+                Some("give the new variable a different name from the one it is initialized with")
+            }
+            ErrorCode::CyclicInheritance => Some("inherit from a different class"),
+            ErrorCode::UndefinedVariable => Some("declare it with 'var' before using it"),
+            ErrorCode::MissingKey => Some("check for the key with has() first"),
+            ErrorCode::StackOverflow => {
+                Some("check that recursive functions have a case that stops recursing")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error or warning about a span of source code, ready to be rendered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// An error, with the help text of its code if there is one.
+    pub fn error(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            notes: vec![],
+            help: code.help().map(Into::into),
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render the diagnostic in the style of rustc, quoting the line of
+    /// `source` that the span starts on and underlining the span with carets.
+    /// `name` identifies the source in the location line. ANSI colours are
+    /// used if `colour` is set.
+    pub fn render(&self, name: &str, source: &str, colour: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if colour {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let line_number = self.span.line.to_string();
+        let pad = " ".repeat(line_number.len());
+        let gutter = paint(BLUE, &format!("{pad} |"));
+
+        let mut output = String::new();
+        let severity = format!("{}[{}]", self.severity.name(), self.code);
+        let _ = writeln!(
+            output,
+            "{}{}",
+            paint(self.severity.colour(), &severity),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        let _ = writeln!(
+            output,
+            "{pad}{} {name}:{}:{}",
+            paint(BLUE, "-->"),
+            self.span.line,
+            self.span.column
+        );
+
+        if let Some(snippet) = Snippet::new(source, self.span) {
+            let carets = "^".repeat(snippet.carets);
+            let _ = writeln!(output, "{gutter}");
+            let _ = writeln!(
+                output,
+                "{} {}",
+                paint(BLUE, &format!("{line_number} |")),
+                snippet.text
+            );
+            let _ = writeln!(
+                output,
+                "{gutter} {}{}",
+                snippet.indent,
+                paint(self.severity.colour(), &carets)
+            );
+        }
+
+        let footer = self
+            .notes
+            .iter()
+            .map(|note| ("note", note))
+            .chain(self.help.iter().map(|help| ("help", help)));
+        for (index, (label, text)) in footer.enumerate() {
+            if index == 0 {
+                let _ = writeln!(output, "{gutter}");
+            }
+            let _ = writeln!(
+                output,
+                "{pad} {} {}: {text}",
+                paint(BLUE, "="),
+                paint(BOLD, label)
+            );
+        }
+
+        output
+    }
+}
+
+/// The line of source a span starts on, and how to underline the span.
+struct Snippet<'a> {
+    text: &'a str,
+    /// Whitespace matching the text before the span, keeping tabs so that the
+    /// carets line up.
+    indent: String,
+    carets: usize,
+}
+
+impl<'a> Snippet<'a> {
+    /// Find the span in `source`, or `None` if it doesn't belong there: the
+    /// REPL, for one, can report errors in code entered on an earlier line.
+    fn new(source: &'a str, span: Span) -> Option<Self> {
+        if span.offset > source.len() || !source.is_char_boundary(span.offset) {
+            return None;
+        }
+        let before = &source[..span.offset];
+        if before.matches('\n').count() + 1 != span.line {
+            return None;
+        }
+
+        let start = before.rfind('\n').map_or(0, |i| i + 1);
+        let end = source[span.offset..]
+            .find('\n')
+            .map_or(source.len(), |i| span.offset + i);
+        // Spans over several lines are underlined to the end of the first.
+        let mut underline_end = span.end().min(end);
+        while !source.is_char_boundary(underline_end) {
+            underline_end -= 1;
+        }
+
+        let indent = source[start..span.offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = source[span.offset..underline_end].chars().count().max(1);
+        Some(Self {
+            text: source[start..end].trim_end_matches('\r'),
+            indent,
+            carets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "var a = 1;\nprint a + \"x\";\n";
+        let diagnostic = Diagnostic::error(
+            ErrorCode::TypeMismatch,
+            Span::new(19, 1, 2, 9),
+            "Operands must be numbers.",
+        )
+        .with_note("'+' adds two numbers or joins two strings");
+        let expected = "\
+error[E0400]: Operands must be numbers.
+ --> test.lox:2:9
+  |
+2 | print a + \"x\";
+  |         ^
+  |
+  = note: '+' adds two numbers or joins two strings
+";
+        assert_eq!(diagnostic.render("test.lox", source, false), expected);
+    }
+
+    #[test]
+    fn test_render_help() {
+        let source = "print \"abc\nd";
+        let diagnostic = Diagnostic::error(
+            ErrorCode::UnterminatedString,
+            Span::new(6, 6, 1, 7),
+            "Unterminated string.",
+        );
+        let expected = "\
+error[E0002]: Unterminated string.
+ --> test.lox:1:7
+  |
+1 | print \"abc
+  |       ^^^^
+  |
+  = help: add a closing '\"' to end the string
+";
+        assert_eq!(diagnostic.render("test.lox", source, false), expected);
+    }
+
+    #[test]
+    fn test_render_keeps_tabs_and_counts_characters() {
+        let source = "\t\"é\" + 1;";
+        let diagnostic =
+            Diagnostic::error(ErrorCode::TypeMismatch, Span::new(1, 4, 1, 2), "message");
+        let rendered = diagnostic.render("test.lox", source, false);
+        assert!(
+            rendered.contains("1 | \t\"é\" + 1;\n  | \t^^^\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_render_without_matching_source() {
+        let diagnostic =
+            Diagnostic::error(ErrorCode::TypeMismatch, Span::new(40, 1, 3, 2), "message");
+        let expected = "error[E0400]: message\n --> test.lox:3:2\n";
+        assert_eq!(diagnostic.render("test.lox", "print 1;", false), expected);
+    }
+
+    #[test]
+    fn test_render_colour() {
+        let diagnostic =
+            Diagnostic::error(ErrorCode::TypeMismatch, Span::new(0, 1, 1, 1), "message");
+        let rendered = diagnostic.render("test.lox", "x", true);
+        assert!(
+            rendered.starts_with("\x1b[1;31merror[E0400]\x1b[0m"),
+            "{rendered}"
+        );
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"), "{rendered}");
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, Function, OpCode};
+
+/// Disassemble `function` and, after it, every function nested inside it.
+pub fn disassemble_function(function: &Function) -> String {
+    let mut output = disassemble_chunk(&function.chunk, &function.to_string());
+    for constant in &function.chunk.constants {
+        if let Constant::Function(nested) = constant {
+            output.push('\n');
+            output.push_str(&disassemble_function(nested));
+        }
+    }
+    output
+}
+
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut output = format!("== {name} ==\n");
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (text, next) = disassemble_instruction(chunk, offset);
+        output.push_str(&text);
+        output.push('\n');
+        offset = next;
+    }
+    output
+}
+
+/// Disassemble the instruction at `offset`, returning its text and the offset
+/// of the following instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let mut text = format!("{offset:04} ");
+    let line = chunk.spans[offset].line;
+    if offset > 0 && line == chunk.spans[offset - 1].line {
+        text.push_str("   | ");
+    } else {
+        let _ = write!(text, "{line:4} ");
+    }
+
+    let byte = chunk.code[offset];
+    let Ok(op) = OpCode::try_from(byte) else {
+        let _ = write!(text, "Unknown opcode {byte}");
+        return (text, offset + 1);
+    };
+    let name = op_name(op);
+
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            let _ = write!(text, "{name:<16} {index:4} '{constant}'");
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::BuildList
+        | OpCode::ExtendList
+        | OpCode::BuildMap
+        | OpCode::ExtendMap => {
+            let operand = chunk.code[offset + 1];
+            let _ = write!(text, "{name:<16} {operand:4}");
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = if op == OpCode::Loop {
+                offset + 3 - jump
+            } else {
+                offset + 3 + jump
+            };
+            let _ = write!(text, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+        OpCode::Invoke | OpCode::SuperInvoke => {
+            let index = chunk.read_u16(offset + 1);
+            let arg_count = chunk.code[offset + 3];
+            let constant = &chunk.constants[index as usize];
+            let _ = write!(text, "{name:<16} ({arg_count} args) {index:4} '{constant}'");
+            offset + 4
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            let _ = write!(text, "{name:<16} {index:4} {constant}");
+            let mut next = offset + 3;
+            if let Constant::Function(function) = constant {
+                for _ in 0..function.upvalue_count {
+                    let kind = if chunk.code[next] == 1 {
+                        "local"
+                    } else {
+                        "upvalue"
+                    };
+                    let index = chunk.code[next + 1];
+                    let _ = write!(text, "\n{next:04}    |                     {kind} {index}");
+                    next += 2;
+                }
+            }
+            next
+        }
+        _ => {
+            text.push_str(&name);
+            offset + 1
+        }
+    };
+
+    (text, next)
+}
+
+/// The conventional name of an opcode, e.g. `OP_GET_LOCAL` for `GetLocal`.
+fn op_name(op: OpCode) -> String {
+    let mut name = String::from("OP");
+    for c in format!("{op:?}").chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compiler::compile, parser::parse, reporter::Collector, resolver::resolve,
+        scanner::scan_tokens,
+    };
+
+    fn compile_source(source: &str) -> std::rc::Rc<Function> {
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        resolve(&mut reporter, &statements);
+        compile(&mut reporter, &statements).unwrap()
+    }
+
+    #[test]
+    fn test_op_name() {
+        assert_eq!(op_name(OpCode::GetLocal), "OP_GET_LOCAL");
+        assert_eq!(op_name(OpCode::Nil), "OP_NIL");
+    }
+
+    #[test]
+    fn test_disassemble_script() {
+        let function = compile_source("var a = 1;\nif (a) print a + 2;");
+        let expected = "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_GET_GLOBAL       1 'a'
+0009    | OP_JUMP_IF_FALSE    9 -> 24
+0012    | OP_POP
+0013    | OP_GET_GLOBAL       1 'a'
+0016    | OP_CONSTANT         2 '2'
+0019    | OP_ADD
+0020    | OP_PRINT
+0021    | OP_JUMP            21 -> 25
+0024    | OP_POP
+0025    | OP_NIL
+0026    | OP_RETURN
+";
+        assert_eq!(disassemble_function(&function), expected);
+    }
+
+    #[test]
+    fn test_disassemble_closure() {
+        let function = compile_source("fun outer() { var x; fun inner() { return x; } }");
+        let output = disassemble_function(&function);
+        assert!(output.contains("== <fn outer> =="), "{output}");
+        assert!(output.contains("== <fn inner> =="), "{output}");
+        assert!(
+            output.contains(
+                "OP_CLOSURE          0 <fn inner>\n0004    |                     local 1"
+            ),
+            "{output}"
+        );
+        assert!(output.contains("OP_GET_UPVALUE      0"), "{output}");
+    }
+}
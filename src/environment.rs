@@ -0,0 +1,157 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    diagnostic::ErrorCode,
+    interpreter::RuntimeError,
+    token::{Token, Value},
+};
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    /// Create a new scope nested inside `enclosing`.
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: impl Into<String>, value: Value) {
+        self.values.insert(name.into(), value);
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(undefined_variable(name)),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(undefined_variable(name)),
+        }
+    }
+
+    /// Look up `name` in this scope only.
+    /// The variables defined in this scope, not counting enclosing ones.
+    pub fn values(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.values.get(name).cloned()
+    }
+
+    /// Look up a name that is known to be defined in this scope, such as
+    /// `this` in a bound method.
+    pub fn get_local(&self, name: &str) -> Value {
+        self.values.get(name).cloned().unwrap_or(Value::None)
+    }
+
+    /// Look up `name` in the scope exactly `distance` levels above this one.
+    pub fn get_at(&self, distance: usize, name: &Token) -> Result<Value, RuntimeError> {
+        let Some(outer) = distance.checked_sub(1) else {
+            return self
+                .values
+                .get(&name.lexeme)
+                .cloned()
+                .ok_or_else(|| undefined_variable(name));
+        };
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get_at(outer, name),
+            None => Err(undefined_variable(name)),
+        }
+    }
+
+    /// Assign to `name` in the scope exactly `distance` levels above this one.
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        name: &Token,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let Some(outer) = distance.checked_sub(1) else {
+            return match self.values.get_mut(&name.lexeme) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => Err(undefined_variable(name)),
+            };
+        };
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(outer, name, value),
+            None => Err(undefined_variable(name)),
+        }
+    }
+}
+
+fn undefined_variable(name: &Token) -> RuntimeError {
+    RuntimeError::new(
+        ErrorCode::UndefinedVariable,
+        name,
+        format!("Undefined variable '{}'.", name.lexeme),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{token::Span, token_type::TokenType};
+
+    fn identifier(name: &str) -> Token {
+        let span = Span::new(0, name.len(), 1, 1);
+        Token::new(TokenType::Identifier, name.into(), Value::None, span)
+    }
+
+    #[test]
+    fn test_define_and_get() {
+        let mut env = Environment::default();
+        env.define("a", 1.0.into());
+        assert_eq!(env.get(&identifier("a")).unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_assign_undefined() {
+        let mut env = Environment::default();
+        let error = env.assign(&identifier("a"), Value::None).unwrap_err();
+        assert_eq!(error.to_string(), "Undefined variable 'a'. [line 1:1]");
+    }
+
+    #[test]
+    fn test_enclosing_scope() {
+        let outer = Rc::new(RefCell::new(Environment::default()));
+        outer.borrow_mut().define("a", 1.0.into());
+        outer.borrow_mut().define("b", 1.0.into());
+
+        let mut inner = Environment::with_enclosing(outer.clone());
+        inner.define("a", 2.0.into());
+        inner.assign(&identifier("b"), 3.0.into()).unwrap();
+
+        assert_eq!(inner.get(&identifier("a")).unwrap(), Value::Number(2.0));
+        assert_eq!(
+            outer.borrow().get(&identifier("a")).unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            outer.borrow().get(&identifier("b")).unwrap(),
+            Value::Number(3.0)
+        );
+    }
+}
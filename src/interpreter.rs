@@ -1,171 +1,986 @@
 #![allow(dead_code)]
 
-use std::fmt::Display;
+use std::{cell::RefCell, collections::HashMap, fmt::Display, io::Write, rc::Rc};
 
 use thiserror::Error;
 
 use crate::{
-    ast::{Expr, ExprVisitor, Stmt, StmtVisitor},
-    lox::LoxReporter,
-    token::{Token, Value},
+    ast::{Depth, Expr, ExprKind, ExprVisitor, Stmt, StmtKind, StmtVisitor},
+    callable::{LoxCallable, LoxFunction},
+    class::{LoxClass, LoxInstance},
+    diagnostic::{Diagnostic, ErrorCode},
+    environment::Environment,
+    list::{get_index, set_index},
+    map::Map,
+    output::Output,
+    reporter::Reporter,
+    stdlib::natives,
+    token::{Span, Token, Value},
     token_type::TokenType,
+    vm::FRAMES_MAX,
 };
 
 #[derive(Error, Debug)]
 pub struct RuntimeError {
-    token: Token,
+    code: ErrorCode,
+    span: Span,
     message: std::string::String,
 }
 
 impl RuntimeError {
-    fn new(token: Token, message: impl Into<String>) -> Self {
+    /// Create an error blamed on `token`.
+    pub(crate) fn new(code: ErrorCode, token: &Token, message: impl Into<String>) -> Self {
+        Self::at(code, token.span, message)
+    }
+
+    /// Create an error for a location that has no token, such as a bytecode
+    /// instruction.
+    pub(crate) fn at(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
         Self {
-            token: token.clone(),
+            code,
+            span,
             message: message.into(),
         }
     }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{0} [line {1}]", self.message, self.token.line)
+        write!(
+            f,
+            "{} [line {}:{}]",
+            self.message, self.span.line, self.span.column
+        )
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Diagnostic::error(error.code, error.span, &error.message)
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Interpreter;
+/// The ways statement execution can stop early: a runtime error, or a
+/// `return` unwinding to the enclosing function call.
+#[derive(Debug)]
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+/// The deepest calls can nest before reporting a stack overflow. Like the
+/// VM's frames, this counts the script as a call, so that both backends
+/// overflow at the same depth. Each call recurses through the interpreter,
+/// so reaching the limit takes far more native stack than a thread has by
+/// default; see [`with_large_stack`](crate::with_large_stack).
+const MAX_CALL_DEPTH: usize = FRAMES_MAX;
+
+/// The deepest statements and expressions can nest while running, counting
+/// across calls. Parsing limits how deeply each function nests, but calls
+/// nested inside deeply nested code could still exhaust the native stack.
+/// Even a debug build reaches this well within [`STACK_SIZE`](crate::STACK_SIZE),
+/// while leaving room for recursion a thousand calls deep.
+const MAX_DEPTH: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    output: Output,
+    /// The number of calls currently running.
+    call_depth: usize,
+    /// The number of statements and expressions currently running.
+    depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let mut environment = Environment::default();
+        for native in natives() {
+            environment.define(native.name().to_string(), native.into());
+        }
+        let globals = Rc::new(RefCell::new(environment));
+        Self {
+            environment: globals.clone(),
+            globals,
+            output: Output::default(),
+            call_depth: 0,
+            depth: 0,
+        }
+    }
+}
 
 impl Interpreter {
     pub fn new() -> Self {
         Default::default()
     }
 
-    pub fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeError> {
-        self.visit_stmt(stmt)
+    /// Create an interpreter that writes the output of `print` statements to
+    /// `output` rather than stdout.
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        let mut interpreter = Self::new();
+        interpreter.set_output(Output::new(output));
+        interpreter
     }
 
-    pub fn interpret(&mut self, reporter: &mut LoxReporter, statements: &Vec<Stmt>) {
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        self.nest(stmt.span)?;
+        let result = self.visit_stmt(stmt);
+        self.depth -= 1;
+        result
+    }
+
+    pub fn interpret(&mut self, reporter: &mut dyn Reporter, statements: &Vec<Stmt>) {
         for statement in statements {
-            if let Err(error) = self.execute(statement) {
-                reporter.runtime_error(error);
-                return;
+            match self.execute(statement) {
+                Ok(()) => (),
+                Err(Unwind::Error(error)) => {
+                    reporter.runtime_error((&error).into());
+                    return;
+                }
+                // A top-level `return` ends the program.
+                Err(Unwind::Return(_)) => return,
             }
         }
     }
 
-    pub fn evaluate(&self, expression: &Expr) -> Result<Value, RuntimeError> {
-        self.visit_expr(expression)
+    /// Execute `statements` in `environment`, restoring the current
+    /// environment afterwards even if execution fails.
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = previous;
+        result
+    }
+
+    fn look_up_variable(&self, name: &Token, depth: &Depth) -> Result<Value, RuntimeError> {
+        match depth.get() {
+            Some(distance) => self.environment.borrow().get_at(distance, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    pub fn evaluate(&mut self, expression: &Expr) -> Result<Value, RuntimeError> {
+        self.nest(expression.span)?;
+        let result = self.visit_expr(expression);
+        self.depth -= 1;
+        result
+    }
+
+    /// Go one statement or expression deeper, failing if that would take
+    /// more native stack than a program can have.
+    fn nest(&mut self, span: Span) -> Result<(), RuntimeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(RuntimeError::at(
+                ErrorCode::StackOverflow,
+                span,
+                "Stack overflow.",
+            ));
+        }
+        self.depth += 1;
+        Ok(())
     }
+
+    /// Define a global variable, replacing any existing one of the same
+    /// name.
+    pub fn define_global(&mut self, name: impl Into<String>, value: Value) {
+        self.globals.borrow_mut().define(name, value);
+    }
+
+    /// Every global variable and its value, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .borrow()
+            .values()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Call the function or class held by the global `name`. Errors have no
+    /// location in the source, as the call doesn't come from it.
+    pub fn call_global(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let Some(callee) = self.globals.borrow().lookup(name) else {
+            return Err(RuntimeError::at(
+                ErrorCode::UndefinedVariable,
+                Span::default(),
+                format!("Undefined variable '{name}'."),
+            ));
+        };
+        self.call(&callee, arguments, Span::default())
+    }
+
+    /// Evaluate one link of a chain, given the value of what it applies to.
+    fn link(&mut self, left: Value, link: &Expr) -> Result<Value, RuntimeError> {
+        match &link.kind {
+            ExprKind::Binary {
+                operator, right, ..
+            } => {
+                let right = self.evaluate(right)?;
+                self.binary(left, operator, right)
+            }
+            ExprKind::Call {
+                paren, arguments, ..
+            } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.call(&left, arguments, paren.span)
+            }
+            ExprKind::Get { name, .. } => match left {
+                Value::Instance(instance) => LoxInstance::get(&instance, name),
+                _ => Err(RuntimeError::new(
+                    ErrorCode::TypeMismatch,
+                    name,
+                    "Only instances have properties.",
+                )),
+            },
+            ExprKind::Index { bracket, index, .. } => {
+                let index = self.evaluate(index)?;
+                get_index(&left, &index, bracket.span)
+            }
+            ExprKind::Logical {
+                operator, right, ..
+            } => {
+                let short_circuit = match operator.token_type {
+                    TokenType::Or => is_truthy(&left),
+                    _ => !is_truthy(&left),
+                };
+                if short_circuit {
+                    Ok(left)
+                } else {
+                    self.evaluate(right)
+                }
+            }
+            _ => Err(RuntimeError::at(
+                ErrorCode::Internal,
+                link.span,
+                "Unexpected link in a chain.",
+            )),
+        }
+    }
+
+    /// Evaluate a binary operation. It is kept out of `visit_expr` so that
+    /// its locals don't add to the native stack taken by every call.
+    fn binary(&self, left: Value, operator: &Token, right: Value) -> Result<Value, RuntimeError> {
+        match operator.token_type {
+            TokenType::Minus => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left - right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::Plus => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+                (Value::String(left), Value::String(right)) => {
+                    let mut result = left.to_string();
+                    result.push_str(&right);
+                    Ok(Value::String(result))
+                }
+                _ => Err(RuntimeError::new(
+                    ErrorCode::TypeMismatch,
+                    operator,
+                    "Operands must be two numbers or two strings.",
+                )),
+            },
+            TokenType::Slash => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left / right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::Star => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left * right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::Greater => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left > right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::GreaterEqual => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left >= right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::Less => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left < right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::LessEqual => match (left, right) {
+                (Value::Number(left), Value::Number(right)) => Ok(Value::Bool(left <= right)),
+                _ => Err(number_operands_error(operator)),
+            },
+            TokenType::BangEqual => Ok(Value::Bool(left != right)),
+            TokenType::EqualEqual => Ok(Value::Bool(left == right)),
+            _ => Err(RuntimeError::new(
+                ErrorCode::Internal,
+                operator,
+                format!("Unexpected binary operator '{}'.", operator.lexeme),
+            )),
+        }
+    }
+
+    /// Call `callee`, reporting errors at `span`.
+    fn call(
+        &mut self,
+        callee: &Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        if let Value::Native(native) = callee {
+            check_arity(native.arity(), arguments.len(), span)?;
+            return native
+                .call(&arguments)
+                .map_err(|message| RuntimeError::at(ErrorCode::NativeError, span, message));
+        }
+        let function: &dyn LoxCallable = match callee {
+            Value::Function(function) => function.as_ref(),
+            Value::Class(class) => class,
+            _ => {
+                return Err(RuntimeError::at(
+                    ErrorCode::NotCallable,
+                    span,
+                    "Can only call functions and classes.",
+                ));
+            }
+        };
+        check_arity(function.arity(), arguments.len(), span)?;
+        if self.call_depth + 1 == MAX_CALL_DEPTH {
+            return Err(RuntimeError::at(
+                ErrorCode::StackOverflow,
+                span,
+                "Stack overflow.",
+            ));
+        }
+        self.call_depth += 1;
+        let result = function.call(self, arguments);
+        self.call_depth -= 1;
+        result
+    }
+}
+
+fn check_arity(arity: usize, count: usize, span: Span) -> Result<(), RuntimeError> {
+    if count == arity {
+        return Ok(());
+    }
+    Err(RuntimeError::at(
+        ErrorCode::ArityMismatch,
+        span,
+        format!("Expected {arity} arguments but got {count}."),
+    ))
 }
 
 #[inline]
 fn number_operands_error(operator: &Token) -> RuntimeError {
-    RuntimeError::new(operator.clone(), "Operands must be numbers.")
+    RuntimeError::new(
+        ErrorCode::TypeMismatch,
+        operator,
+        "Operands must be numbers.",
+    )
 }
 
-impl StmtVisitor<Result<(), RuntimeError>> for Interpreter {
-    fn visit_stmt(&self, stmt: &Stmt) -> Result<(), RuntimeError> {
-        match stmt {
-            Stmt::Expression(expression) => {
+impl StmtVisitor<Result<(), Unwind>> for Interpreter {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        match &stmt.kind {
+            StmtKind::Block(statements) => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                self.execute_block(statements, environment)?;
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let superclass = match superclass {
+                    Some(expr) => match self.evaluate(expr)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            let token = match &expr.kind {
+                                ExprKind::Variable { name, .. } => name,
+                                _ => name,
+                            };
+                            return Err(RuntimeError::new(
+                                ErrorCode::TypeMismatch,
+                                token,
+                                "Superclass must be a class.",
+                            )
+                            .into());
+                        }
+                    },
+                    None => None,
+                };
+
+                self.environment
+                    .borrow_mut()
+                    .define(&name.lexeme, Value::None);
+
+                // Methods of a subclass close over an extra scope holding `super`.
+                let enclosing = self.environment.clone();
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::with_enclosing(enclosing.clone());
+                    environment.define("super", Value::Class(superclass.clone()));
+                    self.environment = Rc::new(RefCell::new(environment));
+                }
+
+                let methods: HashMap<_, _> = methods
+                    .iter()
+                    .map(|method| {
+                        let function = LoxFunction::new(
+                            method.clone(),
+                            self.environment.clone(),
+                            method.name.lexeme == "init",
+                        );
+                        (
+                            method.name.lexeme.clone(),
+                            Value::Function(Rc::new(function)),
+                        )
+                    })
+                    .collect();
+
+                self.environment = enclosing;
+
+                let class = LoxClass::new(&name.lexeme, superclass, methods);
+                self.environment
+                    .borrow_mut()
+                    .assign(name, Value::Class(Rc::new(class)))?;
+            }
+            StmtKind::Expression(expression) => {
                 self.evaluate(expression)?;
             }
-            Stmt::Print(expression) => println!("{}", self.evaluate(expression)?),
+            StmtKind::Function(declaration) => {
+                let function =
+                    LoxFunction::new(declaration.clone(), self.environment.clone(), false);
+                self.environment
+                    .borrow_mut()
+                    .define(&declaration.name.lexeme, Value::Function(Rc::new(function)));
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if is_truthy(&self.evaluate(condition)?) {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            StmtKind::Print(expression) => {
+                let value = self.evaluate(expression)?;
+                self.output.print(&value, stmt.span)?;
+            }
+            StmtKind::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::None,
+                };
+                return Err(Unwind::Return(value));
+            }
+            StmtKind::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::None,
+                };
+                self.environment.borrow_mut().define(&name.lexeme, value);
+            }
+            StmtKind::While { condition, body } => {
+                while is_truthy(&self.evaluate(condition)?) {
+                    self.execute(body)?;
+                }
+            }
         };
         Ok(())
     }
 }
 
 impl ExprVisitor<Result<Value, RuntimeError>> for Interpreter {
-    fn visit_expr(&self, expr: &crate::ast::Expr) -> Result<Value, RuntimeError> {
-        match expr {
-            crate::ast::Expr::Binary {
-                left,
-                operator,
-                right,
+    fn visit_expr(&mut self, expr: &crate::ast::Expr) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExprKind::Assign { name, value, depth } => {
+                let value = self.evaluate(value)?;
+                match depth.get() {
+                    Some(distance) => {
+                        self.environment
+                            .borrow_mut()
+                            .assign_at(distance, name, value.clone())?;
+                    }
+                    None => self.globals.borrow_mut().assign(name, value.clone())?,
+                }
+                Ok(value)
+            }
+            ExprKind::Binary { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Get { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Logical { .. } => {
+                let (first, links) = expr.chain();
+                let mut value = self.evaluate(first)?;
+                for link in links {
+                    value = self.link(value, link)?;
+                }
+                Ok(value)
+            }
+            ExprKind::Grouping(expr) => self.evaluate(expr),
+            ExprKind::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(elements.into())
+            }
+            ExprKind::Literal(value) => Ok(value.clone()),
+            ExprKind::Map(entries) => {
+                let map = Map::new();
+                for (key, value) in entries {
+                    let key = self.evaluate(key)?;
+                    let value = self.evaluate(value)?;
+                    map.insert(key, value).map_err(|message| {
+                        RuntimeError::at(ErrorCode::TypeMismatch, expr.span, message)
+                    })?;
+                }
+                Ok(map.into())
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
             } => {
-                let left = self.visit_expr(left)?;
-                let right = self.visit_expr(right)?;
-                match operator.token_type {
-                    TokenType::Minus => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Number(left - right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::Plus => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Number(left + right))
-                        }
-                        (Value::String(left), Value::String(right)) => {
-                            let mut result = left.to_string();
-                            result.push_str(&right);
-                            Ok(Value::String(result))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::Slash => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Number(left / right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::Star => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Number(left * right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::Greater => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Bool(left > right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::GreaterEqual => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Bool(left >= right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::Less => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Bool(left < right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::LessEqual => match (left, right) {
-                        (Value::Number(left), Value::Number(right)) => {
-                            Ok(Value::Bool(left <= right))
-                        }
-                        _ => Err(number_operands_error(operator)),
-                    },
-                    TokenType::BangEqual => Ok(Value::Bool(left != right)),
-                    TokenType::EqualEqual => Ok(Value::Bool(left == right)),
-                    _ => panic!("Unexpected binary operator!"),
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::new(
+                        ErrorCode::TypeMismatch,
+                        name,
+                        "Only instances have fields.",
+                    ));
+                };
+                let value = self.evaluate(value)?;
+                instance.set(&name.lexeme, value.clone());
+                Ok(value)
+            }
+            ExprKind::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                set_index(&object, &index, value.clone(), bracket.span)?;
+                Ok(value)
+            }
+            ExprKind::Super {
+                keyword,
+                method,
+                depth,
+            } => {
+                // `this` is always bound in the scope just inside `super`'s,
+                // so `super` can't be in the innermost scope.
+                let Some(distance) = depth.get().filter(|&distance| distance > 0) else {
+                    return Err(RuntimeError::new(
+                        ErrorCode::Internal,
+                        keyword,
+                        "'super' wasn't resolved to an enclosing class.",
+                    ));
+                };
+                let Value::Class(superclass) =
+                    self.environment.borrow().get_at(distance, keyword)?
+                else {
+                    return Err(RuntimeError::new(
+                        ErrorCode::TypeMismatch,
+                        keyword,
+                        "Superclass must be a class.",
+                    ));
+                };
+                let this = Token::new(TokenType::This, "this".into(), Value::None, keyword.span);
+                let Value::Instance(object) =
+                    self.environment.borrow().get_at(distance - 1, &this)?
+                else {
+                    return Err(RuntimeError::new(
+                        ErrorCode::Internal,
+                        keyword,
+                        "Undefined 'this'.",
+                    ));
+                };
+
+                match superclass.find_function(&method.lexeme) {
+                    Some(function) => Ok(Value::Function(Rc::new(function.bind(object)))),
+                    None => Err(RuntimeError::new(
+                        ErrorCode::UndefinedProperty,
+                        method,
+                        format!("Undefined property '{}'.", method.lexeme),
+                    )),
                 }
             }
-            Expr::Grouping(expr) => self.visit_expr(expr),
-            Expr::Literal(value) => Ok(value.clone()),
-            Expr::Unary { operator, right } => {
-                let right = self.visit_expr(right)?;
+            ExprKind::This { keyword, depth } => self.look_up_variable(keyword, depth),
+            ExprKind::Unary { operator, right } => {
+                let right = self.evaluate(right)?;
                 match operator.token_type {
-                    TokenType::Bang => Ok(Value::Bool(is_truthy(right))),
+                    TokenType::Bang => Ok(Value::Bool(!is_truthy(&right))),
                     TokenType::Minus => match right {
                         Value::Number(value) => Ok(Value::Number(-value)),
-                        _ => panic!("Unary expression was not a number!"),
+                        _ => Err(RuntimeError::new(
+                            ErrorCode::TypeMismatch,
+                            operator,
+                            "Operand must be a number.",
+                        )),
                     },
-                    _ => panic!("Unexpected unary operator!"), // Unexpected unary operator.
+                    _ => Err(RuntimeError::new(
+                        ErrorCode::Internal,
+                        operator,
+                        format!("Unexpected unary operator '{}'.", operator.lexeme),
+                    )),
                 }
             }
+            ExprKind::Variable { name, depth } => self.look_up_variable(name, depth),
         }
     }
 }
 
-fn is_truthy(value: Value) -> bool {
+pub(crate) fn is_truthy(value: &Value) -> bool {
     match value {
         Value::Number(_) => true,
         Value::String(_) => true,
-        Value::Bool(value) => value,
+        Value::Bool(value) => *value,
         Value::None => false,
+        Value::Function(_) => true,
+        Value::Native(_) => true,
+        Value::Class(_) => true,
+        Value::Instance(_) => true,
+        Value::List(_) => true,
+        Value::Map(_) => true,
+        Value::Closure(_) => true,
+        Value::BoundMethod(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        output::SharedBuffer, parser::parse, reporter::Collector, resolver::resolve,
+        scanner::scan_tokens, token_type::TokenType,
+    };
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Collector {
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        resolve(&mut reporter, &statements);
+        assert!(!reporter.had_error(), "{source}");
+        interpreter.interpret(&mut reporter, &statements);
+        reporter
+    }
+
+    fn get(interpreter: &Interpreter, name: &str) -> Result<Value, RuntimeError> {
+        let span = Span::new(0, name.len(), 1, 1);
+        let token = Token::new(TokenType::Identifier, name.into(), Value::None, span);
+        interpreter.environment.borrow().get(&token)
+    }
+
+    #[test]
+    fn test_globals_persist_between_runs() {
+        let mut interpreter = Interpreter::new();
+        run(&mut interpreter, "var a = 1; var b;");
+        run(&mut interpreter, "a = a + 2;");
+        assert_eq!(get(&interpreter, "a").unwrap(), Value::Number(3.0));
+        assert_eq!(get(&interpreter, "b").unwrap(), Value::None);
+    }
+
+    #[test]
+    fn test_print_writes_to_output() {
+        let output = SharedBuffer::new();
+        let mut interpreter = Interpreter::with_output(output.clone());
+        run(&mut interpreter, "print 1 + 2; print \"a\";");
+        assert_eq!(output.contents(), "3\na\n");
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var a = 1; var b = 1; { var a = 2; b = a; { var c = a; b = b + c; } }",
+        );
+        assert_eq!(get(&interpreter, "a").unwrap(), Value::Number(1.0));
+        assert_eq!(get(&interpreter, "b").unwrap(), Value::Number(4.0));
+        assert!(get(&interpreter, "c").is_err());
+    }
+
+    #[test]
+    fn test_environment_restored_after_error() {
+        let mut interpreter = Interpreter::new();
+        let reporter = run(&mut interpreter, "{ var a = 1; print nil < 1; }");
+        assert!(reporter.had_runtime_error());
+        assert!(get(&interpreter, "a").is_err());
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var total = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+                if (i == 2) total = total + 100; else total = total + i;
+            }
+            var n = 0;
+            while (n < 3) n = n + 1;",
+        );
+        assert_eq!(get(&interpreter, "total").unwrap(), Value::Number(108.0));
+        assert_eq!(get(&interpreter, "n").unwrap(), Value::Number(3.0));
+        assert!(get(&interpreter, "i").is_err());
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var a = nil or \"yes\";
+            var b = 0 and false;
+            var c = false and undefined;
+            var d = true or undefined;
+            var e = !nil;",
+        );
+        assert_eq!(get(&interpreter, "a").unwrap(), Value::from("yes"));
+        assert_eq!(get(&interpreter, "b").unwrap(), Value::Bool(false));
+        assert_eq!(get(&interpreter, "c").unwrap(), Value::Bool(false));
+        assert_eq!(get(&interpreter, "d").unwrap(), Value::Bool(true));
+        assert_eq!(get(&interpreter, "e").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_functions_and_return() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            fun nothing() {}
+            var a = fib(10);
+            var b = nothing();",
+        );
+        assert_eq!(get(&interpreter, "a").unwrap(), Value::Number(55.0));
+        assert_eq!(get(&interpreter, "b").unwrap(), Value::None);
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "fun make_counter() {
+                var i = 0;
+                fun count() { i = i + 1; return i; }
+                return count;
+            }
+            var counter = make_counter();
+            counter();
+            var a = counter();
+            var b = make_counter()();",
+        );
+        assert_eq!(get(&interpreter, "a").unwrap(), Value::Number(2.0));
+        assert_eq!(get(&interpreter, "b").unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_closure_binding_survives_shadowing() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "var a = \"global\";
+            var first;
+            var second;
+            {
+                fun show() { return a; }
+                first = show();
+                var a = \"block\";
+                second = show();
+            }",
+        );
+        assert_eq!(get(&interpreter, "first").unwrap(), Value::from("global"));
+        assert_eq!(get(&interpreter, "second").unwrap(), Value::from("global"));
+    }
+
+    #[test]
+    fn test_classes() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "class Counter {
+                init(start) { this.count = start; }
+                increment() { this.count = this.count + 1; return this; }
+            }
+            var counter = Counter(10);
+            counter.increment().increment();
+            var count = counter.count;
+            var method = counter.increment;
+            method();
+            var bound_count = counter.count;
+            var reinit = counter.init(0);
+            var same = reinit == counter;",
+        );
+        assert_eq!(get(&interpreter, "count").unwrap(), Value::Number(12.0));
+        assert_eq!(
+            get(&interpreter, "bound_count").unwrap(),
+            Value::Number(13.0)
+        );
+        assert_eq!(get(&interpreter, "same").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let mut interpreter = Interpreter::new();
+        run(
+            &mut interpreter,
+            "class A {
+                init(name) { this.name = name; }
+                greet() { return \"A \" + this.name; }
+                inherited() { return \"inherited\"; }
+            }
+            class B < A {
+                greet() { return \"B then \" + super.greet(); }
+            }
+            class C < B {}
+            var c = C(\"c\");
+            var greeting = c.greet();
+            var inherited = c.inherited();",
+        );
+        assert_eq!(
+            get(&interpreter, "greeting").unwrap(),
+            Value::from("B then A c")
+        );
+        assert_eq!(
+            get(&interpreter, "inherited").unwrap(),
+            Value::from("inherited")
+        );
+    }
+
+    #[test]
+    fn test_class_errors() {
+        for (source, message) in [
+            ("var a = 1; a.b;", "Only instances have properties."),
+            ("var a = 1; a.b = 2;", "Only instances have fields."),
+            ("class A {} A().b;", "Undefined property 'b'."),
+            (
+                "class A { init(a) {} } A();",
+                "Expected 1 arguments but got 0.",
+            ),
+        ] {
+            let mut interpreter = Interpreter::new();
+            let mut reporter = Collector::default();
+            let (tokens, _) = scan_tokens(source);
+            let (statements, _) = parse(tokens);
+            resolve(&mut reporter, &statements);
+            let error = statements
+                .iter()
+                .map(|statement| interpreter.execute(statement))
+                .find_map(Result::err);
+            match error {
+                Some(Unwind::Error(error)) => assert_eq!(error.message, message),
+                _ => panic!("Expected a runtime error from {source}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unresolved_super() {
+        // Without the resolver, `super` has no depth to find the class at.
+        let source = "class A { m() {} } class B < A { m() { super.m(); } } B().m();";
+        let mut interpreter = Interpreter::new();
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        interpreter.interpret(&mut reporter, &statements);
+        let error = &reporter.runtime_errors[0];
+        assert_eq!(error.code, ErrorCode::Internal);
+        assert_eq!(
+            error.message,
+            "'super' wasn't resolved to an enclosing class."
+        );
+    }
+
+    #[test]
+    fn test_call_errors() {
+        for source in ["fun f(a) {} f();", "\"not a function\"();"] {
+            let mut interpreter = Interpreter::new();
+            let reporter = run(&mut interpreter, source);
+            assert!(reporter.had_runtime_error(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_operand_errors() {
+        for (source, message, column) in [
+            ("print -\"a\";", "Operand must be a number.", 7),
+            (
+                "print 1 + nil;",
+                "Operands must be two numbers or two strings.",
+                9,
+            ),
+            ("print \"a\" * 2;", "Operands must be numbers.", 11),
+        ] {
+            let mut interpreter = Interpreter::new();
+            let reporter = run(&mut interpreter, source);
+            let error = &reporter.runtime_errors[0];
+            assert_eq!(error.message, message, "{source}");
+            assert_eq!(error.span.column, column, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // Test threads have too little native stack to reach the limit.
+        // Calls within deeply nested code overflow before reaching the call
+        // limit, rather than exhausting the native stack.
+        let nested = format!(
+            "fun f() {{ return {}f(){}; }} f();",
+            "[".repeat(200),
+            "]".repeat(200)
+        );
+        for source in ["fun f() { f(); } f();", &nested] {
+            let message = crate::with_large_stack(|| {
+                let mut interpreter = Interpreter::new();
+                let reporter = run(&mut interpreter, source);
+                reporter.runtime_errors[0].message.clone()
+            });
+            assert_eq!(message.unwrap(), "Stack overflow.");
+        }
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let mut interpreter = Interpreter::new();
+        let reporter = run(&mut interpreter, "var a = 1;\nprint b;");
+        assert!(reporter.had_runtime_error());
+        assert!(get(&interpreter, "b").is_err());
     }
 }
@@ -0,0 +1,57 @@
+//! An interpreter for Lox, the language from *Crafting Interpreters*.
+//!
+//! [`Lox`] runs whole programs, printing their output and reporting errors
+//! through a [`Reporter`]. Each phase can also be used on its own:
+//! [`scan_tokens`] turns source into [`token::Token`]s, [`parse`] builds the
+//! syntax tree in [`ast`], [`resolve`] binds each variable to its declaration,
+//! and [`Interpreter`] runs the result.
+//!
+//! ```
+//! use lox::{Interpreter, parse, reporter::Collector, resolve, scan_tokens};
+//!
+//! let (tokens, scan_errors) = scan_tokens("var a = 1; print a + 2;");
+//! let (statements, parse_errors) = parse(tokens);
+//! assert!(scan_errors.is_empty() && parse_errors.is_empty());
+//!
+//! let mut reporter = Collector::default();
+//! resolve(&mut reporter, &statements);
+//! Interpreter::new().interpret(&mut reporter, &statements);
+//! assert!(!reporter.had_error() && !reporter.had_runtime_error());
+//! ```
+
+pub mod ast;
+mod ast_printer;
+mod callable;
+mod chunk;
+mod class;
+mod compiler;
+pub mod conformance;
+pub mod diagnostic;
+mod disassembler;
+mod environment;
+pub mod interpreter;
+pub mod list;
+mod lox;
+pub mod map;
+pub mod output;
+pub mod parser;
+pub mod reporter;
+pub mod resolver;
+pub mod scanner;
+mod stdlib;
+pub mod token;
+pub mod token_type;
+mod vm;
+
+pub use callable::{LoxFunction, NativeFunction};
+pub use class::{LoxClass, LoxInstance};
+pub use diagnostic::{Diagnostic, ErrorCode};
+pub use interpreter::{Interpreter, RuntimeError};
+pub use lox::{Backend, Lox, LoxError, STACK_SIZE, with_large_stack};
+pub use output::SharedBuffer;
+pub use parser::{ParseError, parse};
+pub use reporter::Reporter;
+pub use resolver::resolve;
+pub use scanner::{ScanError, scan_tokens};
+pub use token::Value;
+pub use vm::{BoundMethod, Closure};
@@ -0,0 +1,202 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fmt::{Debug, Display, Formatter},
+    rc::Rc,
+};
+
+use crate::{
+    diagnostic::ErrorCode,
+    interpreter::RuntimeError,
+    token::{Span, Value},
+};
+
+/// A growable list of values. Clones share the same elements, so a list
+/// changed through one variable is changed for all of them, and like
+/// instances a list is only equal to itself.
+#[derive(Clone, Default)]
+pub struct List(Rc<RefCell<Vec<Value>>>);
+
+impl List {
+    pub fn new(elements: Vec<Value>) -> Self {
+        Self(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn elements(&self) -> Ref<'_, Vec<Value>> {
+        self.0.borrow()
+    }
+
+    pub fn elements_mut(&self) -> RefMut<'_, Vec<Value>> {
+        self.0.borrow_mut()
+    }
+
+    /// The position of the element `index` refers to, counting back from
+    /// the end if it is negative.
+    pub fn position(&self, index: f64) -> Result<usize, String> {
+        let len = self.len();
+        if index.fract() != 0.0 {
+            return Err(format!(
+                "List index must be a whole number, not {}.",
+                Value::Number(index)
+            ));
+        }
+        let position = if index < 0.0 {
+            index + len as f64
+        } else {
+            index
+        };
+        if position < 0.0 || position >= len as f64 {
+            return Err(format!(
+                "List index {} is out of range for a list of length {len}.",
+                Value::Number(index)
+            ));
+        }
+        Ok(position as usize)
+    }
+}
+
+thread_local! {
+    /// The lists and maps being displayed, outermost first, so that one that
+    /// contains itself isn't displayed forever.
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+
+/// Display the collection at `pointer` with `display`, or as `placeholder`
+/// if it is already being displayed further out.
+pub(crate) fn display_once(
+    pointer: *const (),
+    f: &mut Formatter<'_>,
+    placeholder: &str,
+    display: impl FnOnce(&mut Formatter<'_>) -> std::fmt::Result,
+) -> std::fmt::Result {
+    if DISPLAYING.with_borrow(|displaying| displaying.contains(&pointer)) {
+        return f.write_str(placeholder);
+    }
+    DISPLAYING.with_borrow_mut(|displaying| displaying.push(pointer));
+    let result = display(f);
+    DISPLAYING.with_borrow_mut(|displaying| displaying.pop());
+    result
+}
+
+/// Lists display their elements as `[1, "two", nil]`, with strings quoted.
+/// A list inside itself is shown as `[...]`.
+impl Display for List {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        display_once(Rc::as_ptr(&self.0).cast(), f, "[...]", |f| {
+            let elements: Vec<_> = self.elements().iter().map(Value::repr).collect();
+            write!(f, "[{}]", elements.join(", "))
+        })
+    }
+}
+
+impl Debug for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "List({self})")
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Evaluate `object[index]`, where `object` is a list or a map, blaming any
+/// error on `span`.
+pub(crate) fn get_index(object: &Value, index: &Value, span: Span) -> Result<Value, RuntimeError> {
+    if let Value::Map(map) = object {
+        return match map.get(index) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(RuntimeError::at(
+                ErrorCode::MissingKey,
+                span,
+                format!("Key {} is not in the map.", index.repr()),
+            )),
+            Err(message) => Err(RuntimeError::at(ErrorCode::TypeMismatch, span, message)),
+        };
+    }
+    let (list, position) = element(object, index, span)?;
+    Ok(list.elements()[position].clone())
+}
+
+/// Evaluate `object[index] = value`, where `object` is a list or a map,
+/// blaming any error on `span`.
+pub(crate) fn set_index(
+    object: &Value,
+    index: &Value,
+    value: Value,
+    span: Span,
+) -> Result<(), RuntimeError> {
+    if let Value::Map(map) = object {
+        return map
+            .insert(index.clone(), value)
+            .map_err(|message| RuntimeError::at(ErrorCode::TypeMismatch, span, message));
+    }
+    let (list, position) = element(object, index, span)?;
+    list.elements_mut()[position] = value;
+    Ok(())
+}
+
+fn element<'a>(
+    object: &'a Value,
+    index: &Value,
+    span: Span,
+) -> Result<(&'a List, usize), RuntimeError> {
+    let Value::List(list) = object else {
+        return Err(RuntimeError::at(
+            ErrorCode::TypeMismatch,
+            span,
+            format!(
+                "Only lists and maps can be indexed, not a {}.",
+                object.type_name()
+            ),
+        ));
+    };
+    let Value::Number(index) = index else {
+        return Err(RuntimeError::at(
+            ErrorCode::TypeMismatch,
+            span,
+            format!("List index must be a number, not a {}.", index.type_name()),
+        ));
+    };
+    let position = list
+        .position(*index)
+        .map_err(|message| RuntimeError::at(ErrorCode::IndexOutOfRange, span, message))?;
+    Ok((list, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        let list = List::new(vec![1.0.into(), 2.0.into(), 3.0.into()]);
+        assert_eq!(list.position(0.0), Ok(0));
+        assert_eq!(list.position(-1.0), Ok(2));
+        assert_eq!(list.position(-3.0), Ok(0));
+        assert_eq!(
+            list.position(3.0),
+            Err("List index 3 is out of range for a list of length 3.".into())
+        );
+        assert!(list.position(-4.0).is_err());
+        assert!(list.position(0.5).is_err());
+        assert!(list.position(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let list = List::new(vec![1.0.into(), "two".into(), Value::None]);
+        assert_eq!(list.to_string(), "[1, \"two\", nil]");
+        list.elements_mut().push(Value::List(list.clone()));
+        assert_eq!(list.to_string(), "[1, \"two\", nil, [...]]");
+        assert_eq!(List::default().to_string(), "[]");
+    }
+}
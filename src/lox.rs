@@ -1,64 +1,158 @@
 use std::{
-    fs::read_to_string,
-    io::{self, Write},
-    path::Path,
+    convert::Infallible,
+    fmt::{Debug, Write as _},
+    fs::{OpenOptions, read_to_string},
+    io::{self, BufRead, BufReader, Write},
+    panic,
+    path::{Path, PathBuf},
+    thread,
 };
 
 use thiserror::Error;
 
 use crate::{
-    ast::Stmt,
+    ast::{ExprKind, Stmt, StmtKind},
+    ast_printer::AstPrinter,
+    callable::NativeFunction,
+    compiler::compile,
+    diagnostic::Diagnostic,
+    disassembler::disassemble_function,
     interpreter::{Interpreter, RuntimeError},
-    parser::{ParseError, parse},
-    scanner::scan_tokens,
-    token::Token,
+    output::Output,
+    parser::parse,
+    reporter::{Reporter, TextReporter},
+    resolver::resolve,
+    scanner::{ScanErrorKind, scan_tokens},
+    token::{ConversionError, Span, Value},
     token_type::TokenType,
+    vm::Vm,
 };
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum LoxError {
     #[error(transparent)]
     IOError(#[from] io::Error),
     #[error(transparent)]
-    ParseError(#[from] ParseError),
-    #[error(transparent)]
     RuntimeError(#[from] RuntimeError),
+    #[error(transparent)]
+    ConversionError(#[from] ConversionError),
 }
 
-#[derive(Default, Debug)]
-pub struct LoxReporter {
-    pub had_error: bool,
-    pub had_runtime_error: bool,
+/// Lets `Lox::call` return a `Value` as it is.
+impl From<Infallible> for LoxError {
+    fn from(error: Infallible) -> Self {
+        match error {}
+    }
+}
+
+/// Passes diagnostics on to the user's reporter, remembering which kinds
+/// have been seen so the caller can choose an exit code.
+struct Reporting {
+    reporter: Box<dyn Reporter>,
+    had_error: bool,
+    had_runtime_error: bool,
+}
+
+impl Default for Reporting {
+    fn default() -> Self {
+        Self {
+            reporter: Box::new(TextReporter::default()),
+            had_error: false,
+            had_runtime_error: false,
+        }
+    }
+}
+
+impl Reporting {
+    /// Forget the errors seen so far.
+    fn clear(&mut self) {
+        self.had_error = false;
+        self.had_runtime_error = false;
+    }
+}
+
+impl Debug for Reporting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reporting")
+            .field("had_error", &self.had_error)
+            .field("had_runtime_error", &self.had_runtime_error)
+            .finish_non_exhaustive()
+    }
 }
 
-impl LoxReporter {
-    pub(crate) fn error(&mut self, line: usize, message: &str) {
-        self.report(line, "", message)
+impl Reporter for Reporting {
+    fn set_source(&mut self, name: &str, source: &str) {
+        self.reporter.set_source(name, source);
     }
 
-    fn report(&mut self, line: usize, loc: &str, message: &str) {
-        eprintln!("[line {line}] Error {loc} : {message}");
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.reporter.error(diagnostic);
         self.had_error = true;
     }
 
-    pub(crate) fn runtime_error(&mut self, error: RuntimeError) {
-        eprintln!("{error}");
+    fn runtime_error(&mut self, diagnostic: Diagnostic) {
+        self.reporter.runtime_error(diagnostic);
         self.had_runtime_error = true;
     }
+}
 
-    pub(crate) fn parse_error(&mut self, token: &Token, message: &str) {
-        if token.token_type == TokenType::EOF {
-            self.report(token.line, " at end", message);
-        } else {
-            self.report(token.line, &format!(" at '{}'", token.lexeme), message);
-        }
+/// The engine used to execute programs. Both produce the same output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    /// Walk the syntax tree directly.
+    #[default]
+    TreeWalk,
+    /// Compile to bytecode and run it on a stack-based virtual machine.
+    Bytecode,
+}
+
+/// The native stack a program can need. The tree-walk interpreter recurses
+/// for every call and every nested statement and expression, so its deepest
+/// programs need far more than the few megabytes a thread has by default.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Run `f` on a new thread with a stack of [`STACK_SIZE`], waiting for it to
+/// finish. Programs that recurse deeply should be run this way, as the
+/// command line does.
+pub fn with_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> io::Result<T> {
+    thread::scope(|scope| {
+        let handle = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)?;
+        Ok(handle
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic)))
+    })
+}
+
+/// Where the REPL reads lines from, which is stdin unless replaced.
+struct Input(Box<dyn BufRead>);
+
+impl Default for Input {
+    fn default() -> Self {
+        // Holding the lock would block any other `Lox` on the thread.
+        Self(Box::new(BufReader::new(io::stdin())))
+    }
+}
+
+impl Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Input")
     }
 }
 
 #[derive(Default, Debug)]
 pub struct Lox {
+    backend: Backend,
+    disassemble: bool,
     interpreter: Interpreter,
-    pub reporter: LoxReporter,
+    vm: Vm,
+    reporter: Reporting,
+    output: Output,
+    input: Input,
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
 }
 
 impl Lox {
@@ -66,61 +160,895 @@ impl Lox {
         Default::default()
     }
 
+    pub fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            ..Default::default()
+        }
+    }
+
+    /// Send errors to `reporter` rather than printing them to stderr.
+    pub fn set_reporter(&mut self, reporter: impl Reporter + 'static) {
+        self.reporter.reporter = Box::new(reporter);
+    }
+
+    /// Whether an error has been found in a program before running it.
+    pub fn had_error(&self) -> bool {
+        self.reporter.had_error
+    }
+
+    /// Whether a program has stopped with an error while running.
+    pub fn had_runtime_error(&self) -> bool {
+        self.reporter.had_runtime_error
+    }
+
+    /// The exit code for the errors seen so far, following jlox: 65 if a
+    /// program had errors before running, 70 if one stopped with an error,
+    /// and otherwise 0.
+    pub fn exit_code(&self) -> i32 {
+        if self.had_error() {
+            65
+        } else if self.had_runtime_error() {
+            70
+        } else {
+            0
+        }
+    }
+
+    /// Write the output of programs, and the REPL's prompts, to `output`
+    /// rather than stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Output::new(output);
+        self.interpreter.set_output(self.output.clone());
+        self.vm.set_output(self.output.clone());
+    }
+
+    /// Read the REPL's input from `input` rather than stdin.
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Input(Box::new(input));
+    }
+
+    /// Define a global function, written in Rust, that takes `arity`
+    /// arguments. An error it returns is reported at the call as a runtime
+    /// error.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.define_global(name, NativeFunction::new(name, arity, function));
+    }
+
+    /// Define a global variable for programs to use, replacing any existing
+    /// one of the same name.
+    pub fn define_global(&mut self, name: &str, value: impl Into<Value>) {
+        let value = value.into();
+        self.interpreter.define_global(name, value.clone());
+        self.vm.define_global(name, value);
+    }
+
+    /// Call the function or class held by the global `name`, converting its
+    /// result to `T`. Unlike errors in a program, errors in the call are
+    /// returned rather than reported.
+    pub fn call<T>(&mut self, name: &str, arguments: Vec<Value>) -> Result<T, LoxError>
+    where
+        T: TryFrom<Value>,
+        LoxError: From<T::Error>,
+    {
+        let result = match self.backend {
+            Backend::TreeWalk => self.interpreter.call_global(name, arguments)?,
+            Backend::Bytecode => self.vm.call_global(name, arguments)?,
+        };
+        Ok(T::try_from(result)?)
+    }
+
+    /// Print the bytecode of each program to stderr before running it. Only
+    /// applies to the bytecode backend.
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
+
+    /// Print the VM's stack and each instruction to stderr as it executes.
+    /// Only applies to the bytecode backend.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.vm.set_trace(trace);
+    }
+
+    /// Run the script at `path`. Errors in the script are reported rather
+    /// than returned; check `had_error` and `had_runtime_error` afterwards.
     pub fn run_file(&mut self, path: &Path) -> Result<(), LoxError> {
         let code = read_to_string(path)?;
-        self.run(&code)?;
+        self.run_source(&path.display().to_string(), &code)
+    }
 
-        if self.reporter.had_error {
-            std::process::exit(65);
+    /// Keep the REPL's history in the file at `path`: entries from earlier
+    /// sessions are read from it, and each new entry is added to it.
+    pub fn set_history_file(&mut self, path: impl Into<PathBuf>) {
+        self.history_file = Some(path.into());
+    }
+
+    /// Run an interactive session, until the input ends or `:quit` is
+    /// entered. Input is read until its brackets and strings are closed, and
+    /// the value of each expression statement is printed. Lines starting with
+    /// `:` are commands; `:help` lists them. Errors are reported as each entry
+    /// runs, but leave [`exit_code`](Self::exit_code) at 0 afterwards.
+    pub fn run_prompt(&mut self) -> Result<(), LoxError> {
+        self.load_history();
+        let mut entry = String::new();
+        loop {
+            self.output
+                .write_str(if entry.is_empty() { "> " } else { ". " })?;
+            let mut line = String::new();
+            if self.input.0.read_line(&mut line)? == 0 {
+                // Run an unfinished entry anyway, to report what's missing.
+                if !entry.trim().is_empty() {
+                    self.run_entry(&entry)?;
+                }
+                return Ok(());
+            }
+            if entry.is_empty()
+                && let Some(number) = line.trim().strip_prefix(':')
+                && let Ok(number) = number.parse::<usize>()
+            {
+                let Some(recalled) = number
+                    .checked_sub(1)
+                    .and_then(|index| self.history.get(index))
+                else {
+                    self.output
+                        .write_str(&format!("There is no entry {number} in the history.\n"))?;
+                    continue;
+                };
+                // Run the entry again as if it had been typed, showing what it was.
+                line = format!("{recalled}\n");
+                self.output.write_str(&line)?;
+            }
+            if entry.is_empty()
+                && let Some(command) = line.trim().strip_prefix(':')
+            {
+                self.add_history(line.trim());
+                if self.run_command(command)? == Command::Quit {
+                    return Ok(());
+                }
+                continue;
+            }
+            entry.push_str(&line);
+            if is_incomplete(&entry) {
+                continue;
+            }
+            if !entry.trim().is_empty() {
+                self.add_history(entry.trim());
+                self.run_entry(&entry)?;
+            }
+            entry.clear();
         }
+    }
 
-        if self.reporter.had_runtime_error {
-            std::process::exit(70);
+    /// Run an entry typed at the prompt, printing the values of its
+    /// expression statements. A missing `;` at the end is allowed.
+    fn run_entry(&mut self, code: &str) -> Result<(), LoxError> {
+        let statements = try_parse(code).or_else(|| try_parse(&format!("{code};")));
+        let result = match statements {
+            Some(statements) => {
+                self.reporter.set_source("<stdin>", code);
+                self.run_statements(statements.into_iter().map(echo).collect())
+            }
+            None => self.run_source("<stdin>", code),
+        };
+        // A mistake in one entry shouldn't stop the next from running, or
+        // make the session as a whole fail.
+        self.reporter.clear();
+        result
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<Command, LoxError> {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        let text = match name {
+            "q" | "quit" => return Ok(Command::Quit),
+            "help" => HELP.to_string(),
+            "tokens" => self.tokens("<stdin>", argument),
+            "ast" => self.ast("<stdin>", argument),
+            "env" => self
+                .globals()
+                .into_iter()
+                .filter(|(_, value)| !matches!(value, Value::Native(_)))
+                .map(|(name, value)| format!("{name} = {}\n", value.repr()))
+                .collect(),
+            "history" => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| format!("{:4}  {entry}\n", index + 1))
+                .collect(),
+            "load" if !argument.is_empty() => match self.run_file(Path::new(argument)) {
+                Err(LoxError::IOError(error)) => format!("Could not load {argument}: {error}.\n"),
+                result => {
+                    result?;
+                    String::new()
+                }
+            },
+            _ => format!("Unknown command ':{command}'. Enter :help for a list of commands.\n"),
+        };
+        self.reporter.clear();
+        self.output.write_str(&text)?;
+        Ok(Command::Continue)
+    }
+
+    fn load_history(&mut self) {
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        // There's no history yet if the file doesn't exist.
+        if let Ok(history) = read_to_string(path) {
+            self.history.extend(history.lines().map(unescape_history));
         }
+    }
 
-        Ok(())
+    fn add_history(&mut self, entry: &str) {
+        self.history.push(entry.to_string());
+        let Some(path) = &self.history_file else {
+            return;
+        };
+        let saved = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", escape_history(entry)));
+        if let Err(error) = saved {
+            // Say so once, rather than after every entry.
+            let message = format!("Could not save history to {}: {error}.\n", path.display());
+            self.history_file = None;
+            let _ = self.output.write_str(&message);
+        }
     }
 
-    pub fn run_prompt(&mut self) -> Result<(), LoxError> {
-        let mut buffer = String::new();
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
+    /// Every global variable and its value, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        match self.backend {
+            Backend::TreeWalk => self.interpreter.globals(),
+            Backend::Bytecode => self.vm.globals(),
+        }
+    }
 
-        loop {
-            print!("> ");
-            stdout.flush()?;
-            buffer.clear();
-            stdin.read_line(&mut buffer)?;
-            let _ = self.run(&buffer);
+    /// Run a program. Errors in it are reported rather than returned; check
+    /// `had_error` and `had_runtime_error` afterwards. Globals defined by
+    /// earlier programs are still defined.
+    pub fn run(&mut self, code: &str) -> Result<(), LoxError> {
+        self.run_source("<input>", code)
+    }
+
+    /// Run `code`, naming it `name` in error messages.
+    pub fn run_source(&mut self, name: &str, code: &str) -> Result<(), LoxError> {
+        self.reporter.set_source(name, code);
+        let statements = self.parse_code(code);
+        // Statements with syntax errors are missing, so resolving the rest
+        // would only produce misleading errors.
+        if self.reporter.had_error {
+            return Ok(());
         }
+        self.run_statements(statements)
     }
 
-    fn run(&mut self, code: &str) -> Result<(), LoxError> {
-        let statements = self.parse_code(code)?;
-        self.interpreter.interpret(&mut self.reporter, &statements);
+    /// Report any errors in `code` without running it.
+    pub fn check(&mut self, name: &str, code: &str) {
+        self.reporter.set_source(name, code);
+        let statements = self.parse_code(code);
+        if !self.reporter.had_error {
+            resolve(&mut self.reporter, &statements);
+        }
+    }
+
+    /// List the tokens in `code`, one per line, reporting any errors in
+    /// scanning it.
+    pub fn tokens(&mut self, name: &str, code: &str) -> String {
+        self.reporter.set_source(name, code);
+        let (tokens, errors) = scan_tokens(code);
+        for error in &errors {
+            self.reporter.error(error.into());
+        }
+        let mut text = String::new();
+        for token in tokens {
+            let Span { line, column, .. } = token.span;
+            let _ = write!(
+                text,
+                "{line}:{column} {:?} '{}'",
+                token.token_type, token.lexeme
+            );
+            if token.literal != Value::None {
+                let _ = write!(text, " {}", token.literal.repr());
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Print the syntax tree of each statement in `code` on a line of its
+    /// own, reporting any errors in parsing it.
+    pub fn ast(&mut self, name: &str, code: &str) -> String {
+        self.reporter.set_source(name, code);
+        let statements = self.parse_code(code);
+        let mut printer = AstPrinter::new();
+        statements
+            .iter()
+            .map(|stmt| printer.print_stmt(stmt) + "\n")
+            .collect()
+    }
+
+    fn run_statements(&mut self, statements: Vec<Stmt>) -> Result<(), LoxError> {
+        resolve(&mut self.reporter, &statements);
+        if self.reporter.had_error {
+            return Ok(());
+        }
+        match self.backend {
+            Backend::TreeWalk => self.interpreter.interpret(&mut self.reporter, &statements),
+            Backend::Bytecode => {
+                if let Some(function) = compile(&mut self.reporter, &statements) {
+                    if self.disassemble {
+                        eprint!("{}", disassemble_function(&function));
+                    }
+                    self.vm.interpret(&mut self.reporter, function);
+                }
+            }
+        }
         Ok(())
     }
 
-    fn scan_tokens(&mut self, code: &str) -> Vec<Token> {
-        scan_tokens(&mut self.reporter, code)
+    pub fn parse_code(&mut self, code: &str) -> Vec<Stmt> {
+        let (tokens, scan_errors) = scan_tokens(code);
+        for error in &scan_errors {
+            self.reporter.error(error.into());
+        }
+        let (statements, parse_errors) = parse(tokens);
+        for error in &parse_errors {
+            self.reporter.error(error.into());
+        }
+        statements
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Continue,
+    Quit,
+}
+
+const HELP: &str = "\
+:<n>            Run entry <n> of the history again
+:ast <code>     Print the syntax tree of <code>
+:env            List the global variables, other than native functions
+:help           Show this list
+:history        List earlier entries
+:load <path>    Run the script at <path>
+:quit           Leave, as does the end of input
+:tokens <code>  List the tokens in <code>
+";
+
+/// Write a history entry on one line of the history file, escaping the
+/// newlines of multi-line entries and the backslashes that escape them.
+fn escape_history(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Read back an entry written by `escape_history`.
+fn unescape_history(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => entry.push('\n'),
+                Some(c) => entry.push(c),
+                None => entry.push('\\'),
+            },
+            c => entry.push(c),
+        }
     }
+    entry
+}
 
-    pub fn parse_code(&mut self, code: &str) -> Result<Vec<Stmt>, ParseError> {
-        let tokens = self.scan_tokens(code);
-        parse(&mut self.reporter, tokens)
+/// Whether `code` leaves a bracket or string open, so the entry continues
+/// on the next line.
+fn is_incomplete(code: &str) -> bool {
+    let (tokens, errors) = scan_tokens(code);
+    let depth = tokens
+        .iter()
+        .fold(0, |depth, token| match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth + 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth - 1,
+            _ => depth,
+        });
+    depth > 0
+        || errors
+            .iter()
+            .any(|error| error.kind == ScanErrorKind::UnterminatedString)
+}
+
+/// Parse `code`, or give `None` if it has any errors.
+fn try_parse(code: &str) -> Option<Vec<Stmt>> {
+    let (tokens, scan_errors) = scan_tokens(code);
+    let (statements, parse_errors) = parse(tokens);
+    (scan_errors.is_empty() && parse_errors.is_empty()).then_some(statements)
+}
+
+/// Turn an expression statement into one that prints its value, unless it
+/// is an assignment, which is run for its effect.
+fn echo(stmt: Stmt) -> Stmt {
+    match stmt.kind {
+        StmtKind::Expression(expr)
+            if !matches!(
+                expr.kind,
+                ExprKind::Assign { .. } | ExprKind::Set { .. } | ExprKind::SetIndex { .. }
+            ) =>
+        {
+            Stmt::new(StmtKind::Print(expr), stmt.span)
+        }
+        kind => Stmt::new(kind, stmt.span),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
     use super::*;
+    use crate::{
+        diagnostic::ErrorCode,
+        output::SharedBuffer,
+        reporter::{Collector, TextStyle},
+    };
 
     #[test]
     fn test_lox_default() {
         let l: Lox = Default::default();
-        assert!(
-            !l.reporter.had_error,
-            "Lox should be created with no errors."
-        )
+        assert!(!l.had_error(), "Lox should be created with no errors.")
+    }
+
+    #[test]
+    fn test_backends_report_the_same_errors() {
+        let programs = [
+            ("print 1 + 2;", false),
+            ("var a = 1; a.b;", true),
+            ("fun f(a) {} f();", true),
+            ("class A {} class B < A {} B().c();", true),
+        ];
+        for (code, runtime_error) in programs {
+            for backend in [Backend::TreeWalk, Backend::Bytecode] {
+                let mut lox = Lox::with_backend(backend);
+                lox.set_output(SharedBuffer::new());
+                lox.set_reporter(Collector::default());
+                lox.run(code).unwrap();
+                assert_eq!(
+                    lox.had_runtime_error(),
+                    runtime_error,
+                    "{backend:?}: {code}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_embedding() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let mut lox = Lox::with_backend(backend);
+            lox.define_native("twice", 1, |args| match &args[0] {
+                Value::Number(n) => Ok((n * 2.0).into()),
+                _ => Err("Argument must be a number.".into()),
+            });
+            lox.define_global("base", 10.0);
+            lox.run("fun add(a, b) { return base + twice(a) + b; } class C {}")
+                .unwrap();
+            assert!(!lox.had_error() && !lox.had_runtime_error(), "{backend:?}");
+
+            let sum: f64 = lox.call("add", vec![1.0.into(), 2.0.into()]).unwrap();
+            assert_eq!(sum, 14.0, "{backend:?}");
+            let twice: f64 = lox.call("twice", vec![4.0.into()]).unwrap();
+            assert_eq!(twice, 8.0, "{backend:?}");
+            let count: usize = lox.call("twice", vec![3.into()]).unwrap();
+            assert_eq!(count, 6, "{backend:?}");
+            let half: Option<f64> = lox.call("twice", vec![0.25.into()]).unwrap();
+            assert_eq!(half, Some(0.5), "{backend:?}");
+            let error = lox.call::<i64>("twice", vec![0.25.into()]);
+            assert!(
+                matches!(error, Err(LoxError::ConversionError(_))),
+                "{backend:?}"
+            );
+            let instance: Value = lox.call("C", vec![]).unwrap();
+            assert!(matches!(instance, Value::Instance(_)), "{backend:?}");
+
+            let error = lox.call::<String>("add", vec![1.0.into(), 2.0.into()]);
+            assert!(
+                matches!(error, Err(LoxError::ConversionError(_))),
+                "{backend:?}"
+            );
+            let error = lox.call::<Value>("add", vec![]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Expected 2 arguments but got 0. [line 0:0]"
+            );
+            let error = lox.call::<Value>("add", vec!["a".into(), 2.0.into()]);
+            assert!(
+                matches!(error, Err(LoxError::RuntimeError(_))),
+                "{backend:?}"
+            );
+            let error = lox.call::<Value>("missing", vec![]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Undefined variable 'missing'. [line 0:0]"
+            );
+
+            // The backend still works after a failed call.
+            let sum: f64 = lox.call("add", vec![0.0.into(), 0.0.into()]).unwrap();
+            assert_eq!(sum, 10.0, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_native_errors_are_reported_at_the_call() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let output = SharedBuffer::new();
+            let reporter = Rc::new(RefCell::new(Collector::default()));
+            let mut lox = Lox::with_backend(backend);
+            lox.set_output(output.clone());
+            lox.set_reporter(reporter.clone());
+            lox.define_native("fail", 0, |_| Err("It failed.".into()));
+            let error = lox.call::<Value>("fail", vec![]).unwrap_err();
+            assert_eq!(error.to_string(), "It failed. [line 0:0]");
+            lox.run("print 1;\nfail();").unwrap();
+            assert!(lox.had_runtime_error(), "{backend:?}");
+            assert_eq!(output.contents(), "1\n", "{backend:?}");
+
+            let reporter = reporter.borrow();
+            let [diagnostic] = reporter.runtime_errors.as_slice() else {
+                panic!("{backend:?}: {:?}", reporter.runtime_errors);
+            };
+            assert_eq!(diagnostic.code, ErrorCode::NativeError, "{backend:?}");
+            assert_eq!(diagnostic.span.line, 2, "{backend:?}");
+            assert_eq!(diagnostic.message, "It failed.", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_long_chains() {
+        // The unary operators nest, which takes more native stack than test
+        // threads have. The chains are longer than the interpreter can nest.
+        with_large_stack(|| {
+            let programs = [
+                (format!("print {}1;", "1 + ".repeat(300)), "301\n"),
+                (format!("var a = {}1; print a;", "-".repeat(300)), "1\n"),
+                (format!("print {}1;", "1 + ".repeat(20_000)), "20001\n"),
+                (
+                    format!("print {}false or 1;", "true and ".repeat(20_000)),
+                    "1\n",
+                ),
+                (
+                    format!(
+                        "class A {{ a() {{ return [this]; }} }} print A(){};",
+                        ".a()[0]".repeat(20_000)
+                    ),
+                    "A instance\n",
+                ),
+            ];
+            for (code, expected) in programs {
+                for backend in [Backend::TreeWalk, Backend::Bytecode] {
+                    let output = SharedBuffer::new();
+                    let mut lox = Lox::with_backend(backend);
+                    lox.set_output(output.clone());
+                    lox.set_reporter(Collector::default());
+                    lox.run(&code).unwrap();
+                    assert_eq!(output.contents(), expected, "{backend:?}");
+                }
+            }
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_long_literals() {
+        let elements: Vec<_> = (0..600).map(|i| i.to_string()).collect();
+        let entries: Vec<_> = (0..600).map(|i| format!("{i}: -{i}")).collect();
+        let code = format!(
+            "var l = [{}]; print len(l); print l[599];
+            var m = {{{}}}; print len(m); print m[599];",
+            elements.join(", "),
+            entries.join(", ")
+        );
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let output = SharedBuffer::new();
+            let mut lox = Lox::with_backend(backend);
+            lox.set_output(output.clone());
+            lox.run(&code).unwrap();
+            assert_eq!(output.contents(), "600\n599\n600\n-599\n", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_output_and_input_can_be_replaced() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let output = SharedBuffer::new();
+            let mut lox = Lox::with_backend(backend);
+            lox.set_output(output.clone());
+            lox.run("print 1; print nil;").unwrap();
+            assert_eq!(output.contents(), "1\nnil\n", "{backend:?}");
+
+            output.clear();
+            lox.set_input(io::Cursor::new("var a = 2;\nprint a * 3;\n"));
+            lox.run_prompt().unwrap();
+            assert_eq!(output.contents(), "> > 6\n> ", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_check_does_not_run() {
+        let output = SharedBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(output.clone());
+        lox.set_reporter(Collector::default());
+        lox.check("a.lox", "print 1;");
+        assert_eq!(lox.exit_code(), 0);
+        lox.check("b.lox", "print 1; return 2;");
+        assert_eq!(lox.exit_code(), 65);
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn test_repl() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let output = SharedBuffer::new();
+            let errors = SharedBuffer::new();
+            let mut lox = Lox::with_backend(backend);
+            lox.set_output(output.clone());
+            lox.set_reporter(TextReporter::with_writer(errors.clone(), TextStyle::Short));
+            let input = "\
+1 + 2
+var a = \"multi
+line\";
+fun f(x) {
+  return x * 2;
+}
+a = f(3)
+f(a);
+:env
+print oops;
+:ast if (a) { print a; }
+:tokens var b = \"s\"; // done
+:history
+:load missing.lox
+:nonsense
+:quit
+print \"not run\";
+";
+            lox.set_input(io::Cursor::new(input));
+            lox.run_prompt().unwrap();
+            assert_eq!(lox.exit_code(), 0, "{backend:?}");
+            let expected = "\
+> 3
+> . > . . > > 12
+> a = 6
+f = <fn f>
+> > (if a (block (print a)))
+> 1:1 Var 'var'
+1:5 Identifier 'b'
+1:7 Equal '='
+1:9 String '\"s\"' \"s\"
+1:12 Semicolon ';'
+1:21 EOF ''
+>    1  1 + 2
+   2  var a = \"multi
+line\";
+   3  fun f(x) {
+  return x * 2;
+}
+   4  a = f(3)
+   5  f(a);
+   6  :env
+   7  print oops;
+   8  :ast if (a) { print a; }
+   9  :tokens var b = \"s\"; // done
+  10  :history
+> Could not load missing.lox: No such file or directory (os error 2).
+> Unknown command ':nonsense'. Enter :help for a list of commands.
+> ";
+            assert_eq!(output.contents(), expected, "{backend:?}");
+            assert_eq!(
+                errors.contents(),
+                "Undefined variable 'oops'.\n[line 1]\n",
+                "{backend:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_repl_history() {
+        let path = std::env::temp_dir().join(format!("lox-history-{}", std::process::id()));
+        let session = |input: &'static str| {
+            let output = SharedBuffer::new();
+            let mut lox = Lox::new();
+            lox.set_output(output.clone());
+            lox.set_history_file(&path);
+            lox.set_input(io::Cursor::new(input));
+            lox.run_prompt().unwrap();
+            output.contents()
+        };
+
+        session("var a = \"x\\y\nz\";\nprint a;\n");
+        let output = session(":history\n:1\n:2\n:9\n");
+        let expected = "\
+>    1  var a = \"x\\y
+z\";
+   2  print a;
+   3  :history
+> var a = \"x\\y
+z\";
+> print a;
+x\\y
+z
+> There is no entry 9 in the history.
+> ";
+        assert_eq!(output, expected);
+
+        let saved = read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            saved,
+            "var a = \"x\\\\y\\nz\";\nprint a;\n:history\nvar a = \"x\\\\y\\nz\";\nprint a;\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_reports_unfinished_input() {
+        let output = SharedBuffer::new();
+        let errors = SharedBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_output(output.clone());
+        lox.set_reporter(TextReporter::with_writer(errors.clone(), TextStyle::Short));
+        lox.set_input(io::Cursor::new("{\n  print 1;\n"));
+        lox.run_prompt().unwrap();
+        assert_eq!(output.contents(), "> . . ");
+        assert_eq!(
+            errors.contents(),
+            "[line 3] Error at end: Expect '}' after block.\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_does_not_echo_assignments() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let output = SharedBuffer::new();
+            let mut lox = Lox::with_backend(backend);
+            lox.set_output(output.clone());
+            let input = "\
+var a; class C {} var c = C(); var xs = [0];
+a = 1
+c.f = 2
+xs[0] = 3
+[a, c.f, xs[0]]
+";
+            lox.set_input(io::Cursor::new(input));
+            lox.run_prompt().unwrap();
+            assert_eq!(output.contents(), "> > > > > [1, 2, 3]\n> ", "{backend:?}");
+        }
+    }
+
+    /// A xorshift generator, so that the programs `test_run_never_panics`
+    /// tries are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    const SEEDS: [&str; 10] = [
+        "var a = 1 ; print -a + 2 * 3 ;",
+        "fun f ( x ) { return x + 1 ; } print f ( 2 ) ;",
+        "class A { init ( x ) { this . x = x ; } get ( ) { return this . x ; } } print A ( 1 ) . get ( ) ;",
+        "class A { m ( ) { return 1 ; } } class B < A { m ( ) { return super . m ( ) ; } } print B ( ) . m ( ) ;",
+        "{ var a = \"s\" ; { var b = a + a ; print b ; } }",
+        "if ( 1 < 2 and nil or ! true ) print 1 ; else print 2 ;",
+        "fun mk ( ) { var c = 0 ; fun inc ( ) { c = c + 1 ; return c ; } return inc ; } var i = mk ( ) ; i ( ) ; print i ( ) ;",
+        "var x = nil ; x = x == nil ; print x != false / 2 ;",
+        "var l = [ 1 , [ 2 ] ] ; l [ 0 ] = l [ - 1 ] ; push ( l , l ) ; print l [ 1 ] [ 0 ] ;",
+        "var m = { \"a\" : 1 , 2 : { } } ; m [ 2 ] [ 0 ] = m ; print has ( m , \"a\" ) and keys ( m ) ;",
+    ];
+
+    const WORDS: [&str; 43] = [
+        "(", ")", "{", "}", "[", "]", ",", ":", ".", "-", "+", ";", "/", "*", "!", "!=", "=", "==",
+        ">", ">=", "<", "<=", "a", "b", "A", "\"s\"", "1", "0", "and", "class", "else", "false",
+        "fun", "if", "nil", "or", "print", "return", "super", "this", "true", "var", "init",
+    ];
+
+    /// Mangle a seed program by deleting, repeating, swapping and inserting
+    /// tokens. The seeds have no loops and none are added, so every program
+    /// finishes.
+    fn mutate(rng: &mut Rng, seed: &str) -> String {
+        let mut tokens: Vec<&str> = seed.split(' ').collect();
+        for _ in 0..=rng.below(4) {
+            let index = rng.below(tokens.len());
+            match rng.below(4) {
+                0 => {
+                    tokens.remove(index);
+                }
+                1 => tokens.insert(index, tokens[rng.below(tokens.len())]),
+                2 => {
+                    let other = rng.below(tokens.len());
+                    tokens.swap(index, other);
+                }
+                _ => tokens.insert(index, rng.pick(&WORDS)),
+            }
+            if tokens.is_empty() {
+                break;
+            }
+        }
+        tokens.join(" ")
+    }
+
+    #[test]
+    fn test_run_never_panics() {
+        // Deep recursion takes more native stack than test threads have.
+        with_large_stack(|| {
+            let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+            let mut programs: Vec<String> = SEEDS.iter().map(|seed| seed.to_string()).collect();
+            programs.push("fun f ( ) { f ( ) ; } f ( ) ;".into());
+            programs.push("print -\"a\" ; print \"a\" + 1 ; print \"unterminated".into());
+            // Nesting too deep to parse, and calls inside the deepest nesting
+            // that can be.
+            programs.push(format!(
+                "print {}1{};",
+                "(".repeat(50_000),
+                ")".repeat(50_000)
+            ));
+            programs.push(format!("print {}1;", "-".repeat(100_000)));
+            programs.push(format!("{}{}", "{".repeat(100_000), "}".repeat(100_000)));
+            programs.push(format!("{}print 1;", "if (true) ".repeat(100_000)));
+            programs.push(format!(
+                "fun f ( ) {{ return {}f ( ){} ; }} f ( ) ;",
+                "[ ".repeat(2000),
+                " ]".repeat(2000)
+            ));
+            // Chains, which don't nest however long they are.
+            programs.push(format!("print {}1;", "1 + ".repeat(100_000)));
+            programs.push(format!(
+                "print {}nil;",
+                "nil . a ( ) [ 0 ] . ".repeat(10_000)
+            ));
+            for _ in 0..5000 {
+                let seed = rng.pick(&SEEDS);
+                programs.push(mutate(&mut rng, seed));
+            }
+            for _ in 0..200 {
+                let bytes: String = (0..rng.below(20))
+                    .map(|_| char::from_u32(rng.below(0x3000) as u32).unwrap_or('?'))
+                    .collect();
+                programs.push(bytes);
+            }
+
+            for program in &programs {
+                for backend in [Backend::TreeWalk, Backend::Bytecode] {
+                    let mut lox = Lox::with_backend(backend);
+                    lox.set_reporter(Collector::default());
+                    lox.set_output(SharedBuffer::new());
+                    let result =
+                        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| lox.run(program)));
+                    assert!(
+                        matches!(result, Ok(Ok(()))),
+                        "{backend:?} panicked on: {program}"
+                    );
+                }
+            }
+        })
+        .unwrap();
     }
 }
@@ -1,28 +1,174 @@
-use std::{env, path::Path};
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::Path,
+    process,
+};
 
-use eyre::eyre;
+use eyre::{WrapErr, eyre};
+use lox::{
+    Backend, Lox, LoxError, conformance, diagnostic,
+    reporter::{JsonReporter, TextReporter, TextStyle},
+};
 
-mod ast;
-mod ast_printer;
-mod interpreter;
-mod lox;
-mod parser;
-mod scanner;
-mod token;
-mod token_type;
+const USAGE: &str = "\
+Usage: lox [options] [command]
+
+Commands:
+  repl            Start an interactive session, the default
+  [run] <file>    Run a script, reading it from stdin if <file> is -
+  eval <code>     Run <code>, as does -e <code>
+  tokens <file>   List the tokens in a script
+  ast <file>      Print the syntax tree of a script
+  check <file>    Report errors in a script without running it
+  test <dir>      Run the annotated tests in <dir>
+
+Options:
+  --vm                             Run on the bytecode VM
+  --disassemble                    Print the bytecode before running it
+  --trace                          Print each instruction as it runs
+  --error-format=human|json|short  How to print errors
+  -h, --help                       Print this message
+
+Exits with 65 if a script has errors, or 70 if it fails while running.
+";
+
+enum Command {
+    Repl,
+    Run(String),
+    Eval(String),
+    Tokens(String),
+    Ast(String),
+    Check(String),
+    Test(String),
+}
 
 fn main() -> eyre::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mut lox = lox::Lox::new();
-    println!("Args: {args:?}");
-
-    if args.len() > 2 {
-        return Err(eyre!("Usage lox [script]"));
-    } else if args.len() == 2 {
-        lox.run_file(Path::new(&args[1]))?;
-    } else {
-        lox.run_prompt()?;
+    // The main thread's stack is too small for deeply recursive programs.
+    lox::with_large_stack(run).wrap_err("Could not start the interpreter")?
+}
+
+fn run() -> eyre::Result<()> {
+    let mut backend = Backend::TreeWalk;
+    let (mut disassemble, mut trace) = (false, false);
+    let mut error_format = String::from("human");
+    let mut eval = None;
+    let mut positional = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                return Ok(());
+            }
+            "-e" => eval = Some(args.next().ok_or_else(|| eyre!("-e needs code to run"))?),
+            "--vm" => backend = Backend::Bytecode,
+            // Both debugging aids only make sense for the bytecode backend.
+            "--disassemble" => (backend, disassemble) = (Backend::Bytecode, true),
+            "--trace" => (backend, trace) = (Backend::Bytecode, true),
+            "-" => positional.push(arg),
+            _ => {
+                if let Some(format) = arg.strip_prefix("--error-format=") {
+                    error_format = format.to_string();
+                } else if arg.starts_with('-') {
+                    return Err(eyre!("Unknown option {arg}\n\n{USAGE}"));
+                } else {
+                    positional.push(arg);
+                }
+            }
+        }
+    }
+    let positional: Vec<_> = positional.iter().map(String::as_str).collect();
+    let command = match (eval, positional.as_slice()) {
+        (Some(code), []) => Command::Eval(code),
+        (None, ["eval", code]) => Command::Eval(code.to_string()),
+        (None, [] | ["repl"]) => Command::Repl,
+        // Otherwise `lox run` would try to run a file called "run".
+        (None, [command @ ("run" | "eval" | "tokens" | "ast" | "check" | "test")]) => {
+            return Err(eyre!("{command} is missing its argument\n\n{USAGE}"));
+        }
+        (None, ["run", path] | [path]) => Command::Run(path.to_string()),
+        (None, ["tokens", path]) => Command::Tokens(path.to_string()),
+        (None, ["ast", path]) => Command::Ast(path.to_string()),
+        (None, ["check", path]) => Command::Check(path.to_string()),
+        (None, ["test", dir]) => Command::Test(dir.to_string()),
+        _ => return Err(eyre!("Unexpected arguments\n\n{USAGE}")),
+    };
+
+    let mut lox = Lox::with_backend(backend);
+    lox.set_disassemble(disassemble);
+    lox.set_trace(trace);
+    match error_format.as_str() {
+        "human" => {
+            let mut reporter = TextReporter::new(TextStyle::Human);
+            reporter.set_colour(diagnostic::stderr_supports_colour());
+            lox.set_reporter(reporter);
+        }
+        "short" => lox.set_reporter(TextReporter::new(TextStyle::Short)),
+        "json" => lox.set_reporter(JsonReporter::new()),
+        _ => {
+            return Err(eyre!(
+                "Unknown error format {error_format}, expected human, json or short"
+            ));
+        }
+    }
+
+    match command {
+        Command::Repl => {
+            if let Some(home) = env::var_os("HOME") {
+                lox.set_history_file(Path::new(&home).join(".lox_history"));
+            }
+            lox.run_prompt().map_err(report)?;
+        }
+        Command::Run(path) => {
+            let (name, code) = read_script(&path)?;
+            lox.run_source(&name, &code).map_err(report)?;
+        }
+        Command::Eval(code) => lox.run_source("<eval>", &code).map_err(report)?,
+        Command::Tokens(path) => {
+            let (name, code) = read_script(&path)?;
+            print!("{}", lox.tokens(&name, &code));
+        }
+        Command::Ast(path) => {
+            let (name, code) = read_script(&path)?;
+            print!("{}", lox.ast(&name, &code));
+        }
+        Command::Check(path) => {
+            let (name, code) = read_script(&path)?;
+            lox.check(&name, &code);
+        }
+        Command::Test(dir) => {
+            let summary = conformance::run_dir(Path::new(&dir), &[backend])?;
+            println!("{summary}");
+            if !summary.succeeded() {
+                process::exit(1);
+            }
+        }
+    }
+    if lox.exit_code() != 0 {
+        process::exit(lox.exit_code());
     }
 
     Ok(())
 }
+
+/// Read the script at `path`, or from stdin if `path` is `-`, giving the name
+/// to report errors in it under along with its text.
+fn read_script(path: &str) -> eyre::Result<(String, String)> {
+    if path == "-" {
+        let mut code = String::new();
+        io::stdin()
+            .read_to_string(&mut code)
+            .wrap_err("Could not read the script from stdin")?;
+        Ok(("<stdin>".into(), code))
+    } else {
+        let code = fs::read_to_string(path).wrap_err_with(|| format!("Could not read {path}"))?;
+        Ok((path.into(), code))
+    }
+}
+
+/// `LoxError` can hold runtime values, which aren't `Send`, so it can't be
+/// converted into an `eyre::Report` directly.
+fn report(error: LoxError) -> eyre::Report {
+    eyre!("{error}")
+}
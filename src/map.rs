@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use crate::{list::display_once, token::Value};
+
+/// A map from strings and numbers to values. Clones share the same entries,
+/// and like lists a map is only equal to itself.
+///
+/// Entries are kept in the order their keys were first added: replacing a
+/// value leaves it where it was, while removing a key and adding it again
+/// moves it to the end.
+///
+/// Number keys are equal when the numbers are, as with `==`, so `0` and
+/// `-0` are the same key. NaN equals nothing, not even itself, so it can't
+/// be a key. A number key is never equal to a string key: `1` and `"1"` are
+/// different keys.
+#[derive(Clone, Default)]
+pub struct Map(Rc<RefCell<Entries>>);
+
+#[derive(Default)]
+struct Entries {
+    entries: Vec<(Value, Value)>,
+    /// The position in `entries` of each key.
+    positions: HashMap<Key, usize>,
+}
+
+/// A key as it is hashed and compared.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    /// The bits of the number, with `-0` stored as `0`.
+    Number(u64),
+    String(String),
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) if n.is_nan() => Err("NaN can't be a map key.".into()),
+            // Adding zero turns -0 into 0 and leaves every other number alone.
+            Value::Number(n) => Ok(Key::Number((n + 0.0).to_bits())),
+            Value::String(text) => Ok(Key::String(text.clone())),
+            other => Err(format!(
+                "Map keys must be strings or numbers, not a {}.",
+                other.type_name()
+            )),
+        }
+    }
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().entries.is_empty()
+    }
+
+    /// The value of `key`, or `None` if it has none. Fails if `key` can't be
+    /// a key.
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, String> {
+        let key = Key::new(key)?;
+        let entries = self.0.borrow();
+        Ok(entries
+            .positions
+            .get(&key)
+            .map(|&position| entries.entries[position].1.clone()))
+    }
+
+    /// Set the value of `key`, adding it if it is new.
+    pub fn insert(&self, key: Value, value: Value) -> Result<(), String> {
+        let hashed = Key::new(&key)?;
+        let mut entries = self.0.borrow_mut();
+        match entries.positions.get(&hashed) {
+            Some(&position) => entries.entries[position].1 = value,
+            None => {
+                let position = entries.entries.len();
+                entries.entries.push((key, value));
+                entries.positions.insert(hashed, position);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `key`, giving the value it had, if any.
+    pub fn remove(&self, key: &Value) -> Result<Option<Value>, String> {
+        let key = Key::new(key)?;
+        let mut entries = self.0.borrow_mut();
+        let Some(position) = entries.positions.remove(&key) else {
+            return Ok(None);
+        };
+        let (_, value) = entries.entries.remove(position);
+        for later in entries.positions.values_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// The keys and values, in order.
+    pub fn entries(&self) -> Vec<(Value, Value)> {
+        self.0.borrow().entries.clone()
+    }
+}
+
+/// Maps display their entries as `{"a": 1, 2: nil}`, with strings quoted.
+/// A map inside itself is shown as `{...}`.
+impl Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        display_once(Rc::as_ptr(&self.0).cast(), f, "{...}", |f| {
+            let entries: Vec<_> = self
+                .0
+                .borrow()
+                .entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key.repr(), value.repr()))
+                .collect();
+            write!(f, "{{{}}}", entries.join(", "))
+        })
+    }
+}
+
+impl Debug for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Map({self})")
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let map = Map::new();
+        map.insert(0.0.into(), "zero".into()).unwrap();
+        map.insert("0".into(), "string".into()).unwrap();
+        map.insert((-0.0).into(), "negative zero".into()).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&0.0.into()), Ok(Some("negative zero".into())));
+        assert_eq!(map.get(&"0".into()), Ok(Some("string".into())));
+        assert_eq!(map.get(&1.0.into()), Ok(None));
+        assert_eq!(
+            map.insert(f64::NAN.into(), Value::None),
+            Err("NaN can't be a map key.".into())
+        );
+        assert_eq!(
+            map.get(&Value::None),
+            Err("Map keys must be strings or numbers, not a nil.".into())
+        );
+    }
+
+    #[test]
+    fn test_order() {
+        let map = Map::new();
+        for key in ["c", "a", "b"] {
+            map.insert(key.into(), key.into()).unwrap();
+        }
+        map.insert("c".into(), 1.0.into()).unwrap();
+        assert_eq!(map.remove(&"a".into()), Ok(Some("a".into())));
+        assert_eq!(map.remove(&"a".into()), Ok(None));
+        map.insert("a".into(), 2.0.into()).unwrap();
+        assert_eq!(map.to_string(), "{\"c\": 1, \"b\": \"b\", \"a\": 2}");
+        assert_eq!(map.get(&"b".into()), Ok(Some("b".into())));
+
+        map.insert("self".into(), Value::Map(map.clone())).unwrap();
+        assert!(map.to_string().ends_with(", \"self\": {...}}"));
+    }
+}
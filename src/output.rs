@@ -0,0 +1,76 @@
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{diagnostic::ErrorCode, interpreter::RuntimeError, token::Span, token::Value};
+
+/// Where `print` statements write to, which is stdout unless replaced.
+/// Clones share the same sink, so both backends can write to it.
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<dyn Write>>);
+
+impl Output {
+    pub fn new(sink: impl Write + 'static) -> Self {
+        Self(Rc::new(RefCell::new(sink)))
+    }
+
+    /// Write `value` on a line of its own, blaming a failure on `span`.
+    pub(crate) fn print(&self, value: &Value, span: Span) -> Result<(), RuntimeError> {
+        writeln!(self.0.borrow_mut(), "{value}").map_err(|error| {
+            RuntimeError::at(ErrorCode::Io, span, format!("Could not print: {error}."))
+        })
+    }
+
+    /// Write `text` as it is, such as a prompt that input should follow.
+    pub(crate) fn write_str(&self, text: &str) -> io::Result<()> {
+        let mut sink = self.0.borrow_mut();
+        sink.write_all(text.as_bytes())?;
+        sink.flush()
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl Debug for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+/// A buffer that can be written to through one clone and read through
+/// another: give a clone to `Lox::set_output` to capture what a program
+/// prints.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,84 +1,421 @@
 use thiserror::Error;
 
-use crate::ast::Stmt;
-use crate::lox::LoxReporter;
-use crate::token::Value;
+use crate::ast::{FunctionDecl, Stmt};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::token::{Span, Value};
 use crate::token_type::TokenType::{self, *};
-use crate::{ast::Expr, token::Token};
+use crate::{
+    ast::{Expr, ExprKind},
+    token::Token,
+};
 
-#[derive(Error, Debug)]
-pub enum ParseError {
-    #[error("An error occured with parsing.")]
-    Error,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// One of the token types in `ParseError::expected` was required.
+    ExpectedToken,
+    ExpectedExpression,
+    InvalidAssignmentTarget,
+    TooManyParameters,
+    TooManyArguments,
+    TooDeeplyNested,
 }
 
+impl ParseErrorKind {
+    pub fn code(self) -> ErrorCode {
+        match self {
+            ParseErrorKind::ExpectedToken | ParseErrorKind::ExpectedExpression => {
+                ErrorCode::ExpectedToken
+            }
+            ParseErrorKind::InvalidAssignmentTarget => ErrorCode::InvalidAssignmentTarget,
+            ParseErrorKind::TooManyParameters | ParseErrorKind::TooManyArguments => {
+                ErrorCode::TooManyArguments
+            }
+            ParseErrorKind::TooDeeplyNested => ErrorCode::TooDeeplyNested,
+        }
+    }
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("{message} [line {}:{}]", .span.line, .span.column)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The type of the token the error was found at.
+    pub found: TokenType,
+    pub lexeme: std::string::String,
+    pub span: Span,
+    /// The token types that would have been accepted in place of `found`.
+    pub expected: Vec<TokenType>,
+    pub message: std::string::String,
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let diagnostic = Diagnostic::error(error.kind.code(), error.span, &error.message);
+        if error.found == EOF {
+            diagnostic.with_note("reached the end of the file")
+        } else {
+            diagnostic
+        }
+    }
+}
+
+/// The maximum number of parameters a function can declare, and arguments a
+/// call can pass.
+const MAX_ARGUMENTS: usize = 255;
+
+/// The deepest statements and expressions can nest: groupings, unary
+/// operators, blocks and the bodies of statements, functions and classes.
+/// The resolver, compiler and interpreter all recurse through these, as the
+/// parser does, so without a limit deeply nested input would overflow the
+/// native stack. Chains such as `a + b + c` and `a.b().c` are walked without
+/// recursing, so they don't count.
+const MAX_NESTING: usize = 2048;
+
 type ExprResult = Result<Expr, ParseError>;
 type StmtResult = Result<Stmt, ParseError>;
 
 #[derive(Debug)]
 pub struct Parser<'a> {
-    reporter: &'a mut LoxReporter,
     tokens: &'a [Token],
     current: usize,
+    errors: Vec<ParseError>,
+    /// How deeply the construct being parsed is nested.
+    depth: usize,
+    /// Whether the input was nested too deeply, after which the parser gives
+    /// up on the rest of it.
+    too_deep: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(reporter: &'a mut LoxReporter, tokens: &'a [Token]) -> Self {
+    pub fn new(tokens: &'a [Token]) -> Self {
         Self {
-            reporter,
             tokens,
             current: 0,
+            errors: vec![],
+            depth: 0,
+            too_deep: false,
         }
     }
 
-    fn parse(reporter: &'a mut LoxReporter, tokens: &'a [Token]) -> Result<Vec<Stmt>, ParseError> {
-        let mut parser = Self::new(reporter, tokens);
+    fn parse(mut self) -> (Vec<Stmt>, Vec<ParseError>) {
         let mut statements: Vec<Stmt> = vec![];
-        while !parser.is_at_end() {
-            statements.push(parser.statement()?);
+        while !self.is_at_end() {
+            statements.extend(self.declaration());
         }
 
-        Ok(statements)
+        (statements, self.errors)
+    }
+
+    /// Parse a declaration. After a syntax error, record it and skip to the
+    /// next statement so that parsing can carry on and find any further
+    /// errors.
+    ///
+    /// Input nested too deeply is the exception: the rest of it is skipped,
+    /// since carrying on would only report each unclosed bracket around the
+    /// error as it unwinds.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.nested(Self::try_declaration) {
+            Ok(stmt) => Some(stmt),
+            Err(_) if self.too_deep => None,
+            Err(error) => {
+                if error.kind == ParseErrorKind::TooDeeplyNested {
+                    self.too_deep = true;
+                    self.current = self.tokens.len() - 1;
+                } else {
+                    self.synchronize();
+                }
+                self.errors.push(error);
+                None
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> StmtResult {
+        if self.token_match(&[Class]) {
+            return self.class_declaration();
+        }
+        if self.token_match(&[Fun]) {
+            let start = self.previous().span;
+            let declaration = self.function("function")?;
+            return Ok(Stmt::function(declaration, self.span_from(start)));
+        }
+        if self.token_match(&[Var]) {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> StmtResult {
+        let start = self.previous().span;
+        let name = self.consume(Identifier, "Expect class name.")?;
+
+        let superclass = if self.token_match(&[Less]) {
+            let name = self.consume(Identifier, "Expect superclass name.")?;
+            Some(Expr::variable(name))
+        } else {
+            None
+        };
+
+        self.consume(LeftBrace, "Expect '{' before class body.")?;
+
+        let mut methods = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(RightBrace, "Expect '}' after class body.")?;
+
+        Ok(Stmt::class(
+            name,
+            superclass,
+            methods,
+            self.span_from(start),
+        ))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<FunctionDecl, ParseError> {
+        let name = self.consume(Identifier, &format!("Expect {kind} name."))?;
+        self.consume(LeftParen, &format!("Expect '(' after {kind} name."))?;
+        let mut params = vec![];
+        if !self.check(RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    let error = self.error(
+                        ParseErrorKind::TooManyParameters,
+                        self.peek(),
+                        &[],
+                        &format!("Can't have more than {MAX_ARGUMENTS} parameters."),
+                    );
+                    self.errors.push(error);
+                }
+                params.push(self.consume(Identifier, "Expect parameter name.")?);
+                if !self.token_match(&[Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(LeftBrace, &format!("Expect '{{' before {kind} body."))?;
+        let body = self.block()?;
+        Ok(FunctionDecl { name, params, body })
+    }
+
+    fn var_declaration(&mut self) -> StmtResult {
+        let start = self.previous().span;
+        let name = self.consume(Identifier, "Expect variable name.")?;
+        let initializer = if self.token_match(&[Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(Semicolon, "Expect ';' after variable declaration.")?;
+        Ok(Stmt::var(name, initializer, self.span_from(start)))
     }
 
     fn statement(&mut self) -> StmtResult {
+        if self.token_match(&[For]) {
+            return self.for_statement();
+        }
+        if self.token_match(&[If]) {
+            return self.if_statement();
+        }
         if self.token_match(&[Print]) {
             return self.print_statement();
         }
+        if self.token_match(&[Return]) {
+            return self.return_statement();
+        }
+        if self.token_match(&[While]) {
+            return self.while_statement();
+        }
+        if self.token_match(&[LeftBrace]) {
+            let start = self.previous().span;
+            let statements = self.block()?;
+            return Ok(Stmt::block(statements, self.span_from(start)));
+        }
         self.expression_statement()
     }
 
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            statements.extend(self.declaration());
+        }
+        self.consume(RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    /// `for` has no AST node of its own: it is desugared into a `while` loop
+    /// wrapped in blocks for the initializer and increment.
+    fn for_statement(&mut self) -> StmtResult {
+        let start = self.previous().span;
+        self.consume(LeftParen, "Expect '(' after 'for'.")?;
+
+        let initializer = if self.token_match(&[Semicolon]) {
+            None
+        } else if self.token_match(&[Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(Semicolon) {
+            self.expression()?
+        } else {
+            Expr::literal(true, self.peek().span)
+        };
+        self.consume(Semicolon, "Expect ';' after loop condition.")?;
+
+        let increment = if !self.check(RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(RightParen, "Expect ')' after for clauses.")?;
+
+        let mut body = self.nested(Self::statement)?;
+        // The statements introduced by desugaring all cover the whole loop.
+        let span = self.span_from(start);
+        if let Some(increment) = increment {
+            let increment = Stmt::expression(increment, span);
+            body = Stmt::block(vec![body, increment], span);
+        }
+        body = Stmt::while_stmt(condition, body, span);
+        if let Some(initializer) = initializer {
+            body = Stmt::block(vec![initializer, body], span);
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> StmtResult {
+        let start = self.previous().span;
+        self.consume(LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = self.nested(Self::statement)?;
+        let else_branch = if self.token_match(&[Else]) {
+            Some(self.nested(Self::statement)?)
+        } else {
+            None
+        };
+
+        Ok(Stmt::if_stmt(
+            condition,
+            then_branch,
+            else_branch,
+            self.span_from(start),
+        ))
+    }
+
+    fn return_statement(&mut self) -> StmtResult {
+        let keyword = self.previous().clone();
+        let value = if !self.check(Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(Semicolon, "Expect ';' after return value.")?;
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::return_stmt(keyword, value, span))
+    }
+
+    fn while_statement(&mut self) -> StmtResult {
+        let start = self.previous().span;
+        self.consume(LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(RightParen, "Expect ')' after condition.")?;
+        let body = self.nested(Self::statement)?;
+
+        Ok(Stmt::while_stmt(condition, body, self.span_from(start)))
+    }
+
     fn print_statement(&mut self) -> StmtResult {
+        let start = self.previous().span;
         let value = self.expression()?;
         self.consume(Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::print(value))
+        Ok(Stmt::print(value, self.span_from(start)))
     }
 
     fn expression_statement(&mut self) -> StmtResult {
         let expr = self.expression()?;
         self.consume(Semicolon, "Expect ';' after statement.")?;
-        Ok(Stmt::expression(expr))
+        let span = self.span_from(expr.span);
+        Ok(Stmt::expression(expr, span))
     }
 
     fn expression(&mut self) -> ExprResult {
-        self.equality()
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> ExprResult {
+        let mut expr = self.or()?;
+
+        if self.token_match(&[Equal]) {
+            let equals = self.previous().clone();
+            let value = self.nested(Self::assignment)?;
+
+            // Expressions can't be taken apart by moving out of them, as they
+            // implement `Drop`, so take the kind out instead.
+            let kind = std::mem::replace(&mut expr.kind, ExprKind::Literal(Value::None));
+            return match kind {
+                ExprKind::Variable { name, .. } => Ok(Expr::assign(name, value)),
+                ExprKind::Get { object, name } => Ok(Expr::set(*object, name, value)),
+                ExprKind::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Expr::set_index(*object, bracket, *index, value)),
+                kind => {
+                    // Record without unwinding: the parser isn't in a confused state.
+                    let error = self.error(
+                        ParseErrorKind::InvalidAssignmentTarget,
+                        &equals,
+                        &[],
+                        "Invalid assignment target.",
+                    );
+                    self.errors.push(error);
+                    Ok(Expr::new(kind, expr.span))
+                }
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> ExprResult {
+        let mut expr = self.and()?;
+        while self.token_match(&[Or]) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expr::logical(expr, operator, right);
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> ExprResult {
+        let mut expr = self.equality()?;
+        while self.token_match(&[And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::logical(expr, operator, right);
+        }
+        Ok(expr)
     }
 
     fn equality(&mut self) -> ExprResult {
         let mut expr = self.comparison()?;
-
         while self.token_match(&[BangEqual, EqualEqual]) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::binary(expr, operator, right);
         }
-
         Ok(expr)
     }
 
     fn comparison(&mut self) -> ExprResult {
         let mut expr = self.term()?;
-
         while self.token_match(&[Greater, GreaterEqual, Less, LessEqual]) {
             let operator = self.previous().clone();
             let right = self.term()?;
@@ -110,34 +447,155 @@ impl<'a> Parser<'a> {
     fn unary(&mut self) -> ExprResult {
         if self.token_match(&[Bang, Minus]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             return Ok(Expr::unary(operator, right));
         }
-        self.primary()
+        self.call()
+    }
+
+    fn call(&mut self) -> ExprResult {
+        let mut expr = self.primary()?;
+
+        while self.token_match(&[LeftParen, Dot, LeftBracket]) {
+            expr = match self.previous().token_type {
+                LeftParen => self.finish_call(expr)?,
+                Dot => {
+                    let name = self.consume(Identifier, "Expect property name after '.'.")?;
+                    Expr::get(expr, name)
+                }
+                _ => {
+                    let index = self.expression()?;
+                    let bracket = self.consume(RightBracket, "Expect ']' after index.")?;
+                    Expr::index(expr, bracket, index)
+                }
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> ExprResult {
+        let mut arguments = vec![];
+        if !self.check(RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    let error = self.error(
+                        ParseErrorKind::TooManyArguments,
+                        self.peek(),
+                        &[],
+                        &format!("Can't have more than {MAX_ARGUMENTS} arguments."),
+                    );
+                    self.errors.push(error);
+                }
+                arguments.push(self.expression()?);
+                if !self.token_match(&[Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(RightParen, "Expect ')' after arguments.")?;
+
+        Ok(Expr::call(callee, paren, arguments))
     }
 
     fn primary(&mut self) -> ExprResult {
         if self.token_match(&[False]) {
-            return Ok(Expr::literal(false));
+            return Ok(Expr::literal(false, self.previous().span));
         }
         if self.token_match(&[True]) {
-            return Ok(Expr::literal(true));
+            return Ok(Expr::literal(true, self.previous().span));
         }
         if self.token_match(&[Nil]) {
-            return Ok(Expr::literal(Value::None));
+            return Ok(Expr::literal(Value::None, self.previous().span));
         }
 
         if self.token_match(&[Number, String]) {
-            return Ok(Expr::literal(self.previous().literal.clone()));
+            let token = self.previous();
+            return Ok(Expr::literal(token.literal.clone(), token.span));
+        }
+
+        if self.token_match(&[Super]) {
+            let keyword = self.previous().clone();
+            self.consume(Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume(Identifier, "Expect superclass method name.")?;
+            return Ok(Expr::super_expr(keyword, method));
+        }
+
+        if self.token_match(&[This]) {
+            return Ok(Expr::this(self.previous().clone()));
+        }
+
+        if self.token_match(&[Identifier]) {
+            return Ok(Expr::variable(self.previous().clone()));
         }
 
         if self.token_match(&[LeftParen]) {
+            let start = self.previous().span;
             let expr = self.expression()?;
             self.consume(RightParen, "Expect ')' after expression.")?;
-            return Ok(Expr::grouping(expr));
+            return Ok(Expr::grouping(expr, self.span_from(start)));
+        }
+
+        if self.token_match(&[LeftBracket]) {
+            let start = self.previous().span;
+            let mut elements = vec![];
+            if !self.check(RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.token_match(&[Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RightBracket, "Expect ']' after list elements.")?;
+            return Ok(Expr::list(elements, self.span_from(start)));
         }
 
-        Err(self.error(&self.peek().clone(), "Expect expression."))
+        // A statement starting with '{' is a block, so this is only reached
+        // where a block can't be.
+        if self.token_match(&[LeftBrace]) {
+            let start = self.previous().span;
+            let mut entries = vec![];
+            if !self.check(RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(Colon, "Expect ':' after map key.")?;
+                    let value = self.expression()?;
+                    entries.push((key, value));
+                    if !self.token_match(&[Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(RightBrace, "Expect '}' after map entries.")?;
+            return Ok(Expr::map(entries, self.span_from(start)));
+        }
+
+        Err(self.error(
+            ParseErrorKind::ExpectedExpression,
+            self.peek(),
+            &[],
+            "Expect expression.",
+        ))
+    }
+
+    /// Parse with `parse` one level deeper, failing if that is too deep.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error(
+                ParseErrorKind::TooDeeplyNested,
+                self.peek(),
+                &[],
+                &format!("Can't nest more than {MAX_NESTING} levels deep."),
+            ));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
     // -------------------------------------------------------------------------
@@ -146,7 +604,12 @@ impl<'a> Parser<'a> {
         if self.check(token_type) {
             return Ok(self.advance().clone());
         }
-        Err(self.error(&self.peek().clone(), message))
+        Err(self.error(
+            ParseErrorKind::ExpectedToken,
+            self.peek(),
+            &[token_type],
+            message,
+        ))
     }
 
     fn token_match(&mut self, types: &[TokenType]) -> bool {
@@ -185,12 +648,28 @@ impl<'a> Parser<'a> {
         &self.tokens[self.current - 1]
     }
 
-    fn error(&mut self, token: &Token, message: &str) -> ParseError {
-        self.reporter.parse_error(token, message);
-        ParseError::Error
+    /// The span from `start` to the end of the most recently consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
+    }
+
+    fn error(
+        &self,
+        kind: ParseErrorKind,
+        token: &Token,
+        expected: &[TokenType],
+        message: &str,
+    ) -> ParseError {
+        ParseError {
+            kind,
+            found: token.token_type,
+            lexeme: token.lexeme.clone(),
+            span: token.span,
+            expected: expected.to_vec(),
+            message: message.into(),
+        }
     }
 
-    #[allow(dead_code)]
     fn synchronize(&mut self) {
         self.advance();
         while !self.is_at_end() {
@@ -206,47 +685,90 @@ impl<'a> Parser<'a> {
     }
 }
 
-pub fn parse(reporter: &mut LoxReporter, tokens: Vec<Token>) -> Result<Vec<Stmt>, ParseError> {
-    Parser::parse(reporter, &tokens)
+/// Parse as much of `tokens` as possible, returning every syntax error found
+/// alongside the statements.
+///
+/// Only the innermost declaration containing an error is left out: parsing
+/// skips to the next statement and carries on there, so a block or function
+/// body with an error in one of its statements is kept without it. An error
+/// in the block or function itself, such as a missing `}`, leaves out the
+/// whole thing.
+///
+/// `tokens` should end with an EOF token, as [`scan_tokens`] gives; one is
+/// added after the last token if it doesn't.
+///
+/// Parsing input nested close to the limit takes tens of megabytes of native
+/// stack, more in debug builds; see [`with_large_stack`].
+///
+/// [`scan_tokens`]: crate::scan_tokens
+/// [`with_large_stack`]: crate::with_large_stack
+pub fn parse(mut tokens: Vec<Token>) -> (Vec<Stmt>, Vec<ParseError>) {
+    if tokens.last().is_none_or(|token| token.token_type != EOF) {
+        let span = match tokens.last() {
+            Some(last) => Span::new(
+                last.span.end(),
+                0,
+                last.span.line,
+                last.span.column + last.lexeme.chars().count(),
+            ),
+            None => Span::new(0, 0, 1, 1),
+        };
+        tokens.push(Token::new(EOF, "".into(), Value::None, span));
+    }
+    Parser::new(&tokens).parse()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ast::{Expr, Stmt};
-    use crate::lox::LoxReporter;
+    use crate::ast::{Expr, Stmt, StmtKind};
+    use crate::ast_printer::AstPrinter;
     use crate::parser::ExprResult;
     use crate::scanner::scan_tokens;
-    use crate::token::{Token, Value};
+    use crate::token::{Span, Token, Value};
+    use crate::token_type::TokenType;
 
-    use super::Parser;
+    use super::{ParseErrorKind, Parser};
 
     fn parse_expression(expression: &str) -> (ExprResult, bool) {
-        let mut reporter: LoxReporter = Default::default();
-        let tokens = scan_tokens(&mut reporter, expression);
-        let expr = Parser::new(&mut reporter, &tokens).expression();
+        let (tokens, _) = scan_tokens(expression);
+        let mut parser = Parser::new(&tokens);
+        let expr = parser.expression();
+        let had_error = expr.is_err() || !parser.errors.is_empty();
+
+        (expr, had_error)
+    }
+
+    /// The span of `len` bytes at `offset` in a single-line source.
+    fn span(offset: usize, len: usize) -> Span {
+        Span::new(offset, len, 1, offset + 1)
+    }
 
-        (expr, reporter.had_error)
+    fn token(token_type: TokenType, lexeme: &str, offset: usize) -> Token {
+        Token::new(
+            token_type,
+            lexeme.into(),
+            Value::None,
+            span(offset, lexeme.len()),
+        )
+    }
+
+    fn number(value: f64, offset: usize) -> Expr {
+        Expr::literal(value, span(offset, value.to_string().len()))
     }
 
     #[test]
     fn test_expression_statement() {
         let stmt = "1 + 2;";
-        let mut reporter: LoxReporter = Default::default();
-        let tokens = scan_tokens(&mut reporter, stmt);
-        let mut parser = Parser::new(&mut reporter, &tokens);
+        let (tokens, _) = scan_tokens(stmt);
+        let mut parser = Parser::new(&tokens);
         let stmt = parser.statement().unwrap();
 
         let expr2 = Expr::binary(
-            Expr::Literal(1.into()),
-            Token::new(
-                crate::token_type::TokenType::Plus,
-                "+".to_string(),
-                Value::None,
-                1,
-            ),
-            Expr::Literal(2.into()),
+            number(1.0, 0),
+            token(TokenType::Plus, "+", 2),
+            number(2.0, 4),
         );
-        let stmt2 = Stmt::expression(expr2);
+        let stmt2 = Stmt::expression(expr2, span(0, 6));
 
         assert_eq!(stmt, stmt2);
     }
@@ -254,22 +776,16 @@ mod tests {
     #[test]
     fn test_print_statement() {
         let stmt = "print 1 + 2;";
-        let mut reporter: LoxReporter = Default::default();
-        let tokens = scan_tokens(&mut reporter, stmt);
-        let mut parser = Parser::new(&mut reporter, &tokens);
+        let (tokens, _) = scan_tokens(stmt);
+        let mut parser = Parser::new(&tokens);
         let stmt = parser.statement().unwrap();
 
         let expr2 = Expr::binary(
-            Expr::Literal(1.into()),
-            Token::new(
-                crate::token_type::TokenType::Plus,
-                "+".to_string(),
-                Value::None,
-                1,
-            ),
-            Expr::Literal(2.into()),
+            number(1.0, 6),
+            token(TokenType::Plus, "+", 8),
+            number(2.0, 10),
         );
-        let stmt2 = Stmt::print(expr2);
+        let stmt2 = Stmt::print(expr2, span(0, 12));
 
         assert_eq!(stmt, stmt2);
     }
@@ -280,14 +796,9 @@ mod tests {
 
         assert!(!had_error);
         let expr2 = Expr::binary(
-            Expr::Literal(1.into()),
-            Token::new(
-                crate::token_type::TokenType::Plus,
-                "+".to_string(),
-                Value::None,
-                1,
-            ),
-            Expr::Literal(2.into()),
+            number(1.0, 0),
+            token(TokenType::Plus, "+", 2),
+            number(2.0, 4),
         );
 
         assert_eq!(expr.unwrap(), expr2);
@@ -298,14 +809,9 @@ mod tests {
         let (expr, had_error) = parse_expression("1 * 2");
         assert!(!had_error);
         let expr2 = Expr::binary(
-            Expr::Literal(1.into()),
-            Token::new(
-                crate::token_type::TokenType::Star,
-                "*".to_string(),
-                Value::None,
-                1,
-            ),
-            Expr::Literal(2.into()),
+            number(1.0, 0),
+            token(TokenType::Star, "*", 2),
+            number(2.0, 4),
         );
 
         assert_eq!(expr.unwrap(), expr2);
@@ -316,14 +822,9 @@ mod tests {
         let (expr, had_error) = parse_expression("1 / 2");
         assert!(!had_error);
         let expr2 = Expr::binary(
-            Expr::Literal(1.into()),
-            Token::new(
-                crate::token_type::TokenType::Slash,
-                "/".to_string(),
-                Value::None,
-                1,
-            ),
-            Expr::Literal(2.into()),
+            number(1.0, 0),
+            token(TokenType::Slash, "/", 2),
+            number(2.0, 4),
         );
 
         assert_eq!(expr.unwrap(), expr2);
@@ -333,7 +834,7 @@ mod tests {
     fn test_group() {
         let (expr, had_error) = parse_expression("(1)");
         assert!(!had_error);
-        assert_eq!(expr.unwrap(), Expr::grouping(Expr::Literal(1.into())));
+        assert_eq!(expr.unwrap(), Expr::grouping(number(1.0, 1), span(0, 3)));
     }
 
     #[test]
@@ -350,36 +851,311 @@ mod tests {
         let (expr, had_error) = parse_expression("!true");
         assert!(!had_error);
         let expr2 = Expr::unary(
-            Token::new(
-                crate::token_type::TokenType::Bang,
-                "!".into(),
-                Value::None,
-                1,
+            token(TokenType::Bang, "!", 0),
+            Expr::literal(true, span(1, 4)),
+        );
+
+        assert_eq!(expr.unwrap(), expr2);
+    }
+
+    #[test]
+    fn test_var_declaration() {
+        let (tokens, _) = scan_tokens("var a = 1;");
+        let stmt = Parser::new(&tokens).declaration().unwrap();
+
+        let name = token(TokenType::Identifier, "a", 4);
+        assert_eq!(stmt, Stmt::var(name, Some(number(1.0, 8)), span(0, 10)));
+    }
+
+    #[test]
+    fn test_assignment() {
+        let (expr, had_error) = parse_expression("a = b = 1");
+        assert!(!had_error);
+
+        let expr2 = Expr::assign(
+            token(TokenType::Identifier, "a", 0),
+            Expr::assign(token(TokenType::Identifier, "b", 4), number(1.0, 8)),
+        );
+        assert_eq!(expr.unwrap(), expr2);
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let (expr, had_error) = parse_expression("1 = 2");
+        assert!(had_error);
+        assert_eq!(expr.unwrap(), number(1.0, 0));
+    }
+
+    #[test]
+    fn test_block() {
+        let (tokens, _) = scan_tokens("{ 1; { 2; } }");
+        let stmt = Parser::new(&tokens).statement().unwrap();
+
+        let inner = Stmt::block(
+            vec![Stmt::expression(number(2.0, 7), span(7, 2))],
+            span(5, 6),
+        );
+        let outer = Stmt::block(
+            vec![Stmt::expression(number(1.0, 2), span(2, 2)), inner],
+            span(0, 13),
+        );
+        assert_eq!(stmt, outer);
+    }
+
+    #[test]
+    fn test_unterminated_block() {
+        let (tokens, _) = scan_tokens("{ 1;");
+        let error = Parser::new(&tokens).statement().unwrap_err();
+        assert_eq!(error.kind, super::ParseErrorKind::ExpectedToken);
+        assert_eq!(error.expected, [TokenType::RightBrace]);
+        assert_eq!(error.found, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_logical_precedence() {
+        let (expr, had_error) = parse_expression("1 or 2 and 3");
+        assert!(!had_error);
+
+        let expr2 = Expr::logical(
+            number(1.0, 0),
+            token(TokenType::Or, "or", 2),
+            Expr::logical(
+                number(2.0, 5),
+                token(TokenType::And, "and", 7),
+                number(3.0, 11),
             ),
-            Expr::literal(true),
         );
+        assert_eq!(expr.unwrap(), expr2);
+    }
+
+    #[test]
+    fn test_dangling_else() {
+        let (tokens, _) = scan_tokens("if (1) if (2) 3; else 4;");
+        let stmt = Parser::new(&tokens).statement().unwrap();
+
+        let inner = Stmt::if_stmt(
+            number(2.0, 11),
+            Stmt::expression(number(3.0, 14), span(14, 2)),
+            Some(Stmt::expression(number(4.0, 22), span(22, 2))),
+            span(7, 17),
+        );
+        assert_eq!(
+            stmt,
+            Stmt::if_stmt(number(1.0, 4), inner, None, span(0, 24))
+        );
+    }
 
+    #[test]
+    fn test_for_desugars_to_while() {
+        let (tokens, _) = scan_tokens("for (;;) 1;");
+        let stmt = Parser::new(&tokens).statement().unwrap();
+
+        let body = Stmt::expression(number(1.0, 9), span(9, 2));
+        let condition = Expr::literal(true, span(6, 1));
+        assert_eq!(stmt, Stmt::while_stmt(condition, body, span(0, 11)));
+    }
+
+    #[test]
+    fn test_call() {
+        let (expr, had_error) = parse_expression("f(1)(2, 3)");
+        assert!(!had_error);
+
+        let inner = Expr::call(
+            Expr::variable(token(TokenType::Identifier, "f", 0)),
+            token(TokenType::RightParen, ")", 3),
+            vec![number(1.0, 2)],
+        );
+        let expr2 = Expr::call(
+            inner,
+            token(TokenType::RightParen, ")", 9),
+            vec![number(2.0, 5), number(3.0, 8)],
+        );
         assert_eq!(expr.unwrap(), expr2);
     }
 
+    #[test]
+    fn test_too_many_arguments() {
+        let arguments = vec!["1"; 256].join(", ");
+        let (expr, had_error) = parse_expression(&format!("f({arguments})"));
+        assert!(had_error);
+        assert!(expr.is_ok());
+    }
+
+    #[test]
+    fn test_function_declaration() {
+        let (tokens, _) = scan_tokens("fun add(a, b) { return a + b; }");
+        let stmt = Parser::new(&tokens).declaration().unwrap();
+
+        assert_eq!(stmt.span, span(0, 31));
+        let StmtKind::Function(declaration) = stmt.kind else {
+            panic!("Expected a function declaration, got {stmt:?}");
+        };
+        assert_eq!(declaration.name.lexeme, "add");
+        let params: Vec<_> = declaration
+            .params
+            .iter()
+            .map(|p| p.lexeme.as_str())
+            .collect();
+        assert_eq!(params, ["a", "b"]);
+        assert!(matches!(
+            declaration.body[..],
+            [Stmt {
+                kind: StmtKind::Return { .. },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_property_get_and_set() {
+        let (expr, had_error) = parse_expression("a.b.c = this.d");
+        assert!(!had_error);
+
+        let identifier = |name, offset| token(TokenType::Identifier, name, offset);
+        let object = Expr::get(Expr::variable(identifier("a", 0)), identifier("b", 2));
+        let value = Expr::get(
+            Expr::this(token(TokenType::This, "this", 8)),
+            identifier("d", 13),
+        );
+        assert_eq!(expr.unwrap(), Expr::set(object, identifier("c", 4), value));
+    }
+
+    #[test]
+    fn test_class_declaration() {
+        let (tokens, _) = scan_tokens("class A { init(x) {} method() {} }");
+        let stmt = Parser::new(&tokens).declaration().unwrap();
+
+        let StmtKind::Class {
+            name,
+            superclass,
+            methods,
+        } = stmt.kind
+        else {
+            panic!("Expected a class declaration, got {stmt:?}");
+        };
+        assert_eq!(name.lexeme, "A");
+        assert_eq!(superclass, None);
+        let methods: Vec<_> = methods.iter().map(|m| m.name.lexeme.as_str()).collect();
+        assert_eq!(methods, ["init", "method"]);
+    }
+
+    #[test]
+    fn test_subclass_declaration() {
+        let (tokens, _) = scan_tokens("class B < A { m() { super.m(); } }");
+        let stmt = Parser::new(&tokens).declaration().unwrap();
+
+        let StmtKind::Class { superclass, .. } = stmt.kind else {
+            panic!("Expected a class declaration, got {stmt:?}");
+        };
+        let name = token(TokenType::Identifier, "A", 10);
+        assert_eq!(superclass, Some(Expr::variable(name)));
+    }
+
+    #[test]
+    fn test_recovers_after_errors() {
+        let source = "var = 1; print 2; { print ; print 3; } fun (a) {} print 4;";
+        let (tokens, _) = scan_tokens(source);
+        let (statements, errors) = super::parse(tokens);
+        assert_eq!(errors.len(), 3);
+
+        let printer = |stmt: &Stmt| match &stmt.kind {
+            StmtKind::Print(expr) => AstPrinter.print(expr),
+            StmtKind::Block(statements) => format!("{} in block", statements.len()),
+            _ => panic!("Unexpected statement {stmt:?}"),
+        };
+        let parsed: Vec<_> = statements.iter().map(printer).collect();
+        assert_eq!(parsed, ["2", "1 in block", "4"]);
+    }
+
+    #[test]
+    fn test_adds_missing_eof() {
+        assert_eq!(super::parse(vec![]), (vec![], vec![]));
+
+        let (mut tokens, _) = scan_tokens("print 1");
+        tokens.pop();
+        let (statements, errors) = super::parse(tokens);
+        assert!(statements.is_empty());
+        assert_eq!(errors[0].found, TokenType::EOF);
+        assert_eq!(errors[0].span, span(7, 0));
+    }
+
+    #[test]
+    fn test_too_deeply_nested() {
+        // Parsing near the limit takes more native stack than test threads have.
+        crate::with_large_stack(check_too_deeply_nested).unwrap();
+    }
+
+    fn check_too_deeply_nested() {
+        let depth = super::MAX_NESTING + 1;
+        for source in [
+            format!("print {}1{};", "(".repeat(depth), ")".repeat(depth)),
+            format!("print {}1;", "-".repeat(depth)),
+            format!("{}{}", "{".repeat(depth), "}".repeat(depth)),
+            format!("{}print 1;", "if (true) ".repeat(depth)),
+            format!("{}{}", "fun f() {".repeat(depth), "}".repeat(depth)),
+        ] {
+            let (tokens, _) = scan_tokens(&source);
+            let (statements, errors) = super::parse(tokens);
+            assert!(statements.is_empty());
+            assert_eq!(errors.len(), 1, "{source}");
+            assert_eq!(errors[0].kind, ParseErrorKind::TooDeeplyNested);
+        }
+
+        let source = format!("print {}1{};", "(".repeat(2000), ")".repeat(2000));
+        let (tokens, _) = scan_tokens(&source);
+        assert!(super::parse(tokens).1.is_empty());
+    }
+
+    #[test]
+    fn test_chains_are_not_nesting() {
+        for source in [
+            format!("print {}1;", "1 + ".repeat(100_000)),
+            format!("print {}1;", "true and ".repeat(100_000)),
+            format!("print a{};", ".b()[0]".repeat(100_000)),
+        ] {
+            let (tokens, _) = scan_tokens(&source);
+            let (statements, errors) = super::parse(tokens);
+            assert!(errors.is_empty());
+            assert_eq!(statements.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_super_requires_method() {
+        let (expr, had_error) = parse_expression("super");
+        assert!(had_error);
+        assert!(expr.is_err());
+    }
+
     #[test]
     fn test_precedence() {
         let (expr, had_error) = parse_expression("1 * 2 + 3 / 4");
         assert!(!had_error);
 
-        let left = parse_expression("1 * 2").0.unwrap();
-        let right = parse_expression("3 / 4").0.unwrap();
-        let expr2 = Expr::binary(
-            left,
-            Token::new(
-                crate::token_type::TokenType::Plus,
-                "+".to_string(),
-                Value::None,
-                1,
-            ),
-            right,
+        let left = Expr::binary(
+            number(1.0, 0),
+            token(TokenType::Star, "*", 2),
+            number(2.0, 4),
         );
+        let right = Expr::binary(
+            number(3.0, 8),
+            token(TokenType::Slash, "/", 10),
+            number(4.0, 12),
+        );
+        let expr2 = Expr::binary(left, token(TokenType::Plus, "+", 6), right);
 
         assert_eq!(expr.unwrap(), expr2);
     }
+
+    #[test]
+    fn test_spans_across_lines() {
+        let (expr, had_error) = parse_expression("(a +\n  bc)");
+        assert!(!had_error);
+        let expr = expr.unwrap();
+        assert_eq!(expr.span, Span::new(0, 10, 1, 1));
+        let crate::ast::ExprKind::Grouping(inner) = &expr.kind else {
+            panic!("Expected a grouping, got {expr:?}");
+        };
+        assert_eq!(inner.span, Span::new(1, 8, 1, 2));
+    }
 }
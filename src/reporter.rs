@@ -0,0 +1,374 @@
+use std::{
+    cell::RefCell,
+    io::{self, Stderr, Write},
+    rc::Rc,
+};
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+
+/// Receives the errors found in a program. Errors found before the program
+/// runs, which stop it from running at all, are kept apart from the error
+/// that stops a running program, so that callers can tell them apart.
+pub trait Reporter {
+    /// Set the name and text of the source that following diagnostics refer
+    /// to.
+    fn set_source(&mut self, _name: &str, _source: &str) {}
+
+    /// Report an error found before the program runs.
+    fn error(&mut self, diagnostic: Diagnostic);
+
+    /// Report the error that stopped the program while it ran.
+    fn runtime_error(&mut self, diagnostic: Diagnostic);
+}
+
+/// How `TextReporter` lays out each diagnostic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextStyle {
+    /// In the style of rustc, quoting and underlining the source.
+    #[default]
+    Human,
+    /// One line per error, in the style of jlox: `[line N] Error at 'x': ...`.
+    Short,
+}
+
+/// Writes diagnostics as text, to stderr unless given another writer.
+#[derive(Debug)]
+pub struct TextReporter<W: Write = Stderr> {
+    out: W,
+    style: TextStyle,
+    colour: bool,
+    name: String,
+    source: String,
+}
+
+impl Default for TextReporter {
+    fn default() -> Self {
+        Self::new(TextStyle::Human)
+    }
+}
+
+impl TextReporter {
+    pub fn new(style: TextStyle) -> Self {
+        Self::with_writer(io::stderr(), style)
+    }
+}
+
+impl<W: Write> TextReporter<W> {
+    pub fn with_writer(out: W, style: TextStyle) -> Self {
+        Self {
+            out,
+            style,
+            colour: false,
+            name: String::new(),
+            source: String::new(),
+        }
+    }
+
+    /// Colour diagnostics with ANSI escape codes. Only the human style is
+    /// coloured.
+    pub fn set_colour(&mut self, colour: bool) {
+        self.colour = colour;
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn name(&self) -> &str {
+        if self.name.is_empty() {
+            "<input>"
+        } else {
+            &self.name
+        }
+    }
+
+    /// Where the error was found, as jlox puts it: `at 'x'` for the text of
+    /// the span, or `at end` for the end of the source. Like jlox, errors
+    /// from the scanner aren't given a location.
+    fn location(&self, diagnostic: &Diagnostic) -> String {
+        let span = diagnostic.span;
+        if matches!(
+            diagnostic.code,
+            ErrorCode::UnexpectedCharacter | ErrorCode::UnterminatedString
+        ) {
+            String::new()
+        } else if span.offset >= self.source.len() && span.len == 0 {
+            " at end".into()
+        } else {
+            match self.source.get(span.offset..span.end()) {
+                Some(text) if !text.is_empty() => format!(" at '{text}'"),
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        // There is nowhere left to report a failure to report an error.
+        let _ = self.out.write_all(text.as_bytes());
+    }
+}
+
+impl<W: Write> Reporter for TextReporter<W> {
+    fn set_source(&mut self, name: &str, source: &str) {
+        self.name = name.into();
+        self.source = source.into();
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        let text = match self.style {
+            TextStyle::Human => diagnostic.render(self.name(), &self.source, self.colour),
+            TextStyle::Short => format!(
+                "[line {}] Error{}: {}\n",
+                diagnostic.span.line,
+                self.location(&diagnostic),
+                diagnostic.message
+            ),
+        };
+        self.write(&text);
+    }
+
+    fn runtime_error(&mut self, diagnostic: Diagnostic) {
+        let text = match self.style {
+            TextStyle::Human => diagnostic.render(self.name(), &self.source, self.colour),
+            TextStyle::Short => {
+                format!("{}\n[line {}]\n", diagnostic.message, diagnostic.span.line)
+            }
+        };
+        self.write(&text);
+    }
+}
+
+/// Writes each diagnostic as a JSON object on a line of its own, for editors
+/// and CI to read. The fields are `file`, `kind` (`"error"` or
+/// `"runtime_error"`), `severity`, `code`, `message`, `line`, `column`,
+/// `offset` and `length` (in bytes), `notes` and `help` (or `null`).
+#[derive(Debug)]
+pub struct JsonReporter<W: Write = Stderr> {
+    out: W,
+    name: String,
+}
+
+impl Default for JsonReporter {
+    fn default() -> Self {
+        Self::with_writer(io::stderr())
+    }
+}
+
+impl JsonReporter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<W: Write> JsonReporter<W> {
+    pub fn with_writer(out: W) -> Self {
+        Self {
+            out,
+            name: String::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, kind: &str, diagnostic: &Diagnostic) {
+        let notes: Vec<_> = diagnostic
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect();
+        let help = diagnostic
+            .help
+            .as_deref()
+            .map_or_else(|| "null".into(), json_string);
+        let line = format!(
+            "{{\"file\":{},\"kind\":\"{kind}\",\"severity\":\"{}\",\"code\":\"{}\",\
+             \"message\":{},\"line\":{},\"column\":{},\"offset\":{},\"length\":{},\
+             \"notes\":[{}],\"help\":{help}}}\n",
+            json_string(&self.name),
+            diagnostic.severity.name(),
+            diagnostic.code,
+            json_string(&diagnostic.message),
+            diagnostic.span.line,
+            diagnostic.span.column,
+            diagnostic.span.offset,
+            diagnostic.span.len,
+            notes.join(","),
+        );
+        // There is nowhere left to report a failure to report an error.
+        let _ = self.out.write_all(line.as_bytes());
+    }
+}
+
+impl<W: Write> Reporter for JsonReporter<W> {
+    fn set_source(&mut self, name: &str, _source: &str) {
+        self.name = name.into();
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.write("error", &diagnostic);
+    }
+
+    fn runtime_error(&mut self, diagnostic: Diagnostic) {
+        self.write("runtime_error", &diagnostic);
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if u32::from(c) < 0x20 => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Keeps diagnostics in memory rather than writing them anywhere, so that
+/// tests can inspect them.
+#[derive(Default, Debug)]
+pub struct Collector {
+    pub errors: Vec<Diagnostic>,
+    pub runtime_errors: Vec<Diagnostic>,
+}
+
+impl Collector {
+    pub fn had_error(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn had_runtime_error(&self) -> bool {
+        !self.runtime_errors.is_empty()
+    }
+}
+
+impl Reporter for Collector {
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.errors.push(diagnostic);
+    }
+
+    fn runtime_error(&mut self, diagnostic: Diagnostic) {
+        self.runtime_errors.push(diagnostic);
+    }
+}
+
+/// A shared reporter, so that its owner can still inspect it after handing a
+/// clone to [`Lox`](crate::Lox).
+impl<R: Reporter + ?Sized> Reporter for Rc<RefCell<R>> {
+    fn set_source(&mut self, name: &str, source: &str) {
+        self.borrow_mut().set_source(name, source);
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.borrow_mut().error(diagnostic);
+    }
+
+    fn runtime_error(&mut self, diagnostic: Diagnostic) {
+        self.borrow_mut().runtime_error(diagnostic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Span;
+
+    const SOURCE: &str = "var a = 1;\nprint a + \"x\"\n";
+
+    fn report(reporter: &mut dyn Reporter) {
+        reporter.set_source("test.lox", SOURCE);
+        reporter.error(Diagnostic::error(
+            ErrorCode::ExpectedToken,
+            Span::new(25, 0, 3, 1),
+            "Expect ';' after value.",
+        ));
+        reporter.error(Diagnostic::error(
+            ErrorCode::UndefinedVariable,
+            Span::new(4, 1, 1, 5),
+            "Say \"hi\"\tfirst.",
+        ));
+        reporter.error(Diagnostic::error(
+            ErrorCode::UnexpectedCharacter,
+            Span::new(0, 1, 1, 1),
+            "Unexpected character.",
+        ));
+        reporter.runtime_error(
+            Diagnostic::error(
+                ErrorCode::TypeMismatch,
+                Span::new(19, 1, 2, 9),
+                "Operands must be numbers.",
+            )
+            .with_note("a note"),
+        );
+    }
+
+    #[test]
+    fn test_short() {
+        let mut reporter = TextReporter::with_writer(vec![], TextStyle::Short);
+        report(&mut reporter);
+        let output = String::from_utf8(reporter.into_inner()).unwrap();
+        let expected = "\
+[line 3] Error at end: Expect ';' after value.
+[line 1] Error at 'a': Say \"hi\"\tfirst.
+[line 1] Error: Unexpected character.
+Operands must be numbers.
+[line 2]
+";
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_human() {
+        let mut reporter = TextReporter::with_writer(vec![], TextStyle::Human);
+        report(&mut reporter);
+        let output = String::from_utf8(reporter.into_inner()).unwrap();
+        assert!(output.starts_with("error[E0100]: Expect ';' after value.\n --> test.lox:3:1\n"));
+        assert!(
+            output.contains("2 | print a + \"x\"\n  |         ^\n"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let mut reporter = JsonReporter::with_writer(vec![]);
+        report(&mut reporter);
+        let output = String::from_utf8(reporter.into_inner()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "{\"file\":\"test.lox\",\"kind\":\"error\",\"severity\":\"error\",\"code\":\"E0401\",\
+             \"message\":\"Say \\\"hi\\\"\\tfirst.\",\"line\":1,\"column\":5,\"offset\":4,\"length\":1,\
+             \"notes\":[],\"help\":\"declare it with 'var' before using it\"}"
+        );
+        assert!(
+            lines[3].contains("\"kind\":\"runtime_error\""),
+            "{}",
+            lines[3]
+        );
+        assert!(
+            lines[3].contains("\"notes\":[\"a note\"],\"help\":null}"),
+            "{}",
+            lines[3]
+        );
+    }
+
+    #[test]
+    fn test_collector() {
+        let mut collector = Collector::default();
+        report(&mut collector);
+        assert_eq!(collector.errors.len(), 3);
+        assert!(collector.had_runtime_error());
+        assert_eq!(collector.runtime_errors[0].code, ErrorCode::TypeMismatch);
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Depth, Expr, ExprKind, ExprVisitor, FunctionDecl, Stmt, StmtKind, StmtVisitor},
+    diagnostic::{Diagnostic, ErrorCode},
+    reporter::Reporter,
+    token::Token,
+};
+
+/// Resolve every local variable reference in `statements`, recording how many
+/// scopes up it is declared. Semantic errors are reported to `reporter`.
+pub fn resolve(reporter: &mut dyn Reporter, statements: &[Stmt]) {
+    Resolver::new(reporter).resolve_stmts(statements);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+struct Resolver<'a> {
+    reporter: &'a mut dyn Reporter,
+    /// Local scopes, innermost last. Each maps a name to whether its
+    /// initializer has finished resolving.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(reporter: &'a mut dyn Reporter) -> Self {
+        Self {
+            reporter,
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+        }
+    }
+
+    fn resolve_stmts(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.visit_stmt(statement);
+        }
+    }
+
+    fn resolve_function(&mut self, function: &FunctionDecl, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_stmts(&function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains_key(&name.lexeme) {
+            self.reporter.error(Diagnostic::error(
+                ErrorCode::DuplicateDeclaration,
+                name.span,
+                "Already a variable with this name in this scope.",
+            ));
+        }
+        scope.insert(name.lexeme.clone(), false);
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Token, depth: &Depth) {
+        if let Some(distance) = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name.lexeme))
+        {
+            depth.set(distance);
+        }
+    }
+}
+
+impl StmtVisitor<()> for Resolver<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                self.resolve_stmts(statements);
+                self.end_scope();
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let ExprKind::Variable {
+                        name: superclass_name,
+                        ..
+                    } = &superclass.kind
+                        && superclass_name.lexeme == name.lexeme
+                    {
+                        self.reporter.error(Diagnostic::error(
+                            ErrorCode::CyclicInheritance,
+                            superclass_name.span,
+                            "A class can't inherit from itself.",
+                        ));
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.visit_expr(superclass);
+
+                    self.begin_scope();
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert("super".to_string(), true);
+                    }
+                }
+
+                self.begin_scope();
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert("this".to_string(), true);
+                }
+                for method in methods {
+                    let function_type = if method.name.lexeme == "init" {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(method, function_type);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            }
+            StmtKind::Expression(expression) => self.visit_expr(expression),
+            StmtKind::Function(declaration) => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionType::Function);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(condition);
+                self.visit_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+            }
+            StmtKind::Print(expression) => self.visit_expr(expression),
+            StmtKind::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.reporter.error(Diagnostic::error(
+                        ErrorCode::InvalidReturn,
+                        keyword.span,
+                        "Can't return from top-level code.",
+                    ));
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.reporter.error(Diagnostic::error(
+                            ErrorCode::InvalidReturn,
+                            keyword.span,
+                            "Can't return a value from an initializer.",
+                        ));
+                    }
+                    self.visit_expr(value);
+                }
+            }
+            StmtKind::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.visit_expr(initializer);
+                }
+                self.define(name);
+            }
+            StmtKind::While { condition, body } => {
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+            }
+        }
+    }
+}
+
+impl ExprVisitor<()> for Resolver<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Assign { name, value, depth } => {
+                self.visit_expr(value);
+                self.resolve_local(name, depth);
+            }
+            ExprKind::Binary { .. }
+            | ExprKind::Call { .. }
+            | ExprKind::Get { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Logical { .. } => {
+                let (first, links) = expr.chain();
+                self.visit_expr(first);
+                for link in links {
+                    match &link.kind {
+                        ExprKind::Binary { right, .. } | ExprKind::Logical { right, .. } => {
+                            self.visit_expr(right)
+                        }
+                        ExprKind::Call { arguments, .. } => {
+                            for argument in arguments {
+                                self.visit_expr(argument);
+                            }
+                        }
+                        ExprKind::Index { index, .. } => self.visit_expr(index),
+                        _ => (),
+                    }
+                }
+            }
+            ExprKind::Grouping(expr) => self.visit_expr(expr),
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.visit_expr(element);
+                }
+            }
+            ExprKind::Literal(_) => (),
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.visit_expr(key);
+                    self.visit_expr(value);
+                }
+            }
+            ExprKind::Set { object, value, .. } => {
+                self.visit_expr(value);
+                self.visit_expr(object);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.visit_expr(object);
+                self.visit_expr(index);
+                self.visit_expr(value);
+            }
+            ExprKind::Super { keyword, depth, .. } => {
+                match self.current_class {
+                    ClassType::None => self.reporter.error(Diagnostic::error(
+                        ErrorCode::InvalidSuper,
+                        keyword.span,
+                        "Can't use 'super' outside of a class.",
+                    )),
+                    ClassType::Class => self.reporter.error(Diagnostic::error(
+                        ErrorCode::InvalidSuper,
+                        keyword.span,
+                        "Can't use 'super' in a class with no superclass.",
+                    )),
+                    ClassType::Subclass => (),
+                }
+                self.resolve_local(keyword, depth);
+            }
+            ExprKind::This { keyword, depth } => {
+                if self.current_class == ClassType::None {
+                    self.reporter.error(Diagnostic::error(
+                        ErrorCode::InvalidThis,
+                        keyword.span,
+                        "Can't use 'this' outside of a class.",
+                    ));
+                    return;
+                }
+                self.resolve_local(keyword, depth);
+            }
+            ExprKind::Unary { right, .. } => self.visit_expr(right),
+            ExprKind::Variable { name, depth } => {
+                if self.scopes.last().and_then(|scope| scope.get(&name.lexeme)) == Some(&false) {
+                    self.reporter.error(Diagnostic::error(
+                        ErrorCode::SelfReferentialInitializer,
+                        name.span,
+                        "Can't read local variable in its own initializer.",
+                    ));
+                }
+                self.resolve_local(name, depth);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse, reporter::Collector, scanner::scan_tokens};
+
+    fn resolve_source(source: &str) -> (Vec<Stmt>, Collector) {
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        resolve(&mut reporter, &statements);
+        (statements, reporter)
+    }
+
+    #[test]
+    fn test_depths() {
+        let (statements, reporter) = resolve_source("var a; { var b; { a; b; } }");
+        assert!(!reporter.had_error());
+
+        let StmtKind::Block(outer) = &statements[1].kind else {
+            panic!("Expected a block");
+        };
+        let StmtKind::Block(inner) = &outer[1].kind else {
+            panic!("Expected a block");
+        };
+        let depths: Vec<_> = inner
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Expression(Expr {
+                    kind: ExprKind::Variable { depth, .. },
+                    ..
+                }) => depth.get(),
+                _ => panic!("Expected a variable expression"),
+            })
+            .collect();
+        assert_eq!(depths, [None, Some(1)]);
+    }
+
+    #[test]
+    fn test_errors() {
+        for source in [
+            "{ var a = a; }",
+            "{ var a; var a; }",
+            "fun f(a, a) {}",
+            "return 1;",
+            "print this;",
+            "fun f() { this; }",
+            "class A { init() { return 1; } }",
+            "class A < A {}",
+            "super.m();",
+            "class A { m() { super.m(); } }",
+        ] {
+            let (_, reporter) = resolve_source(source);
+            assert!(reporter.had_error(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_valid_programs() {
+        for source in [
+            "var a = 1; var a = a;",
+            "{ var a; { var a = 1; } }",
+            "fun f() { return 1; }",
+            "class A { init() { return; } method() { return this; } }",
+            "class A {} class B < A { m() { return super.m; } }",
+        ] {
+            let (_, reporter) = resolve_source(source);
+            assert!(!reporter.had_error(), "{source}");
+        }
+    }
+}
@@ -2,8 +2,10 @@ use std::collections::HashMap;
 use std::string::String;
 use std::sync::OnceLock;
 
-use crate::lox::LoxReporter;
-use crate::token::{Token, Value};
+use thiserror::Error;
+
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::token::{Span, Token, Value};
 use crate::token_type::TokenType::{self, *};
 
 fn keywords() -> &'static HashMap<String, TokenType> {
@@ -31,17 +33,54 @@ fn keywords() -> &'static HashMap<String, TokenType> {
     })
 }
 
-pub fn scan_tokens(reporter: &mut LoxReporter, source: &str) -> Vec<Token> {
-    Scanner::new(source).scan_tokens(reporter)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+}
+
+impl ScanErrorKind {
+    pub fn code(self) -> ErrorCode {
+        match self {
+            ScanErrorKind::UnexpectedCharacter(_) => ErrorCode::UnexpectedCharacter,
+            ScanErrorKind::UnterminatedString => ErrorCode::UnterminatedString,
+        }
+    }
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("{message} [line {}:{}]", .span.line, .span.column)]
+pub struct ScanError {
+    pub kind: ScanErrorKind,
+    pub span: Span,
+    pub message: String,
 }
 
+impl From<&ScanError> for Diagnostic {
+    fn from(error: &ScanError) -> Self {
+        Diagnostic::error(error.kind.code(), error.span, &error.message)
+    }
+}
+
+/// Split `source` into tokens, ending with an EOF token. Characters that
+/// can't start a token are skipped, and reported in the returned errors.
+pub fn scan_tokens(source: &str) -> (Vec<Token>, Vec<ScanError>) {
+    Scanner::new(source).scan_tokens()
+}
+
+/// `start` and `current` index into `source` by character; `offset` tracks
+/// the byte offset of `current` so that spans can be given in bytes.
 #[derive(Default, Debug)]
 struct Scanner {
     source: Vec<char>,
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize,
+    offset: usize,
+    token_start: Span,
     tokens: Vec<Token>,
+    errors: Vec<ScanError>,
 }
 
 impl Scanner {
@@ -57,11 +96,19 @@ impl Scanner {
         self.current >= self.source.len()
     }
 
+    /// The span from the start of the current token to `current`.
+    fn span(&self) -> Span {
+        Span {
+            len: self.offset - self.token_start.offset,
+            ..self.token_start
+        }
+    }
+
     // TODO: Can we change Into<Value> to Value?
     fn add_token_literal(&mut self, t: TokenType, literal: impl Into<Value>) {
         let text: String = self.source[self.start..self.current].iter().collect();
         self.tokens
-            .push(Token::new(t, text, literal.into(), self.line));
+            .push(Token::new(t, text, literal.into(), self.span()));
     }
 
     fn add_token(&mut self, t: TokenType) {
@@ -69,8 +116,14 @@ impl Scanner {
     }
 
     fn advance(&mut self) -> char {
+        let c = self.source[self.current];
         self.current += 1;
-        self.source[self.current - 1]
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.current;
+        }
+        c
     }
 
     fn char_match(&mut self, expected: char) -> bool {
@@ -80,7 +133,7 @@ impl Scanner {
         if self.source[self.current] != expected {
             return false;
         }
-        self.current += 1;
+        self.advance();
         true
     }
 
@@ -98,13 +151,24 @@ impl Scanner {
         self.source[self.current + 1]
     }
 
-    fn scan_token(&mut self, reporter: &mut LoxReporter) {
+    fn error(&mut self, kind: ScanErrorKind, message: &str) {
+        self.errors.push(ScanError {
+            kind,
+            span: self.span(),
+            message: message.into(),
+        });
+    }
+
+    fn scan_token(&mut self) {
         let c = self.advance();
         match c {
             '(' => self.add_token(LeftParen),
             ')' => self.add_token(RightParen),
             '{' => self.add_token(LeftBrace),
             '}' => self.add_token(RightBrace),
+            '[' => self.add_token(LeftBracket),
+            ']' => self.add_token(RightBracket),
+            ':' => self.add_token(Colon),
             ',' => self.add_token(Comma),
             '.' => self.add_token(Dot),
             '-' => self.add_token(Minus),
@@ -152,16 +216,18 @@ impl Scanner {
                     self.add_token(Slash);
                 }
             }
-            ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
-            '"' => self.string(reporter),
+            ' ' | '\r' | '\t' | '\n' => (),
+            '"' => self.string(),
             _ => {
                 if is_digit(c) {
                     self.number();
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    reporter.error(self.line, "Unexpected character.");
+                    self.error(
+                        ScanErrorKind::UnexpectedCharacter(c),
+                        "Unexpected character.",
+                    );
                 }
             }
         };
@@ -197,15 +263,12 @@ impl Scanner {
         self.add_token_literal(Number, value);
     }
 
-    fn string(&mut self, reporter: &mut LoxReporter) {
+    fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
             self.advance();
         }
         if self.is_at_end() {
-            reporter.error(self.line, "Unterminated string.");
+            self.error(ScanErrorKind::UnterminatedString, "Unterminated string.");
             return;
         }
         self.advance(); // the closing '"'
@@ -215,15 +278,22 @@ impl Scanner {
         self.add_token_literal(String, value);
     }
 
-    fn scan_tokens(mut self, reporter: &mut LoxReporter) -> Vec<Token> {
+    fn start_token(&mut self) {
+        self.start = self.current;
+        let column = self.current - self.line_start + 1;
+        self.token_start = Span::new(self.offset, 0, self.line, column);
+    }
+
+    fn scan_tokens(mut self) -> (Vec<Token>, Vec<ScanError>) {
         while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token(reporter);
+            self.start_token();
+            self.scan_token();
         }
 
+        self.start_token();
         self.tokens
-            .push(Token::new(EOF, "".into(), Value::None, self.line));
-        self.tokens
+            .push(Token::new(EOF, "".into(), Value::None, self.span()));
+        (self.tokens, self.errors)
     }
 }
 
@@ -245,10 +315,58 @@ mod tests {
 
     #[test]
     fn test_identifier() {
-        let t1 = Token::new(Or, "or".into(), Value::None, 1);
-        let t2 = Token::new(Identifier, "thing".into(), Value::None, 1);
-        let tokens = scan_tokens(&mut Default::default(), "or thing");
+        let t1 = Token::new(Or, "or".into(), Value::None, Span::new(0, 2, 1, 1));
+        let t2 = Token::new(
+            Identifier,
+            "thing".into(),
+            Value::None,
+            Span::new(3, 5, 1, 4),
+        );
+        let (tokens, _) = scan_tokens("or thing");
         assert_eq!(tokens[0], t1);
         assert_eq!(tokens[1], t2);
     }
+
+    #[test]
+    fn test_spans() {
+        let (tokens, _) = scan_tokens("var s = \"é\nb\";\n  x >= 1");
+        let spans: Vec<_> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            [
+                Span::new(0, 3, 1, 1),
+                Span::new(4, 1, 1, 5),
+                Span::new(6, 1, 1, 7),
+                Span::new(8, 6, 1, 9),
+                Span::new(14, 1, 2, 3),
+                Span::new(18, 1, 3, 3),
+                Span::new(20, 2, 3, 5),
+                Span::new(23, 1, 3, 8),
+                Span::new(24, 0, 3, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let (tokens, errors) = scan_tokens("1 @ 2\n\"open");
+        let types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(types, [Number, Number, EOF]);
+        assert_eq!(
+            errors,
+            [
+                ScanError {
+                    kind: ScanErrorKind::UnexpectedCharacter('@'),
+                    span: Span::new(2, 1, 1, 3),
+                    message: "Unexpected character.".into(),
+                },
+                ScanError {
+                    kind: ScanErrorKind::UnterminatedString,
+                    span: Span::new(6, 5, 2, 1),
+                    message: "Unterminated string.".into(),
+                },
+            ]
+        );
+        assert_eq!(errors[0].to_string(), "Unexpected character. [line 1:3]");
+    }
 }
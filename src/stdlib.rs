@@ -0,0 +1,220 @@
+//! The functions every program can call, which both backends define as
+//! globals. Calls have already been checked against each function's arity;
+//! an argument of the wrong type is an error reported at the call.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{callable::NativeFunction, list::List, map::Map, token::Value};
+
+/// A new instance of each function in the standard library.
+pub(crate) fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("clock", 0, |_| {
+            let elapsed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|error| format!("The clock is before 1970: {error}."))?;
+            Ok(elapsed.as_secs_f64().into())
+        }),
+        math("sqrt", f64::sqrt),
+        math("floor", f64::floor),
+        math("sin", f64::sin),
+        math("abs", f64::abs),
+        NativeFunction::new("pow", 2, |args| {
+            Ok(number("pow", args, 0)?.powf(number("pow", args, 1)?).into())
+        }),
+        NativeFunction::new("min", 2, |args| {
+            Ok(number("min", args, 0)?.min(number("min", args, 1)?).into())
+        }),
+        NativeFunction::new("max", 2, |args| {
+            Ok(number("max", args, 0)?.max(number("max", args, 1)?).into())
+        }),
+        NativeFunction::new("len", 1, |args| match &args[0] {
+            Value::String(text) => Ok(text.chars().count().into()),
+            Value::List(list) => Ok(list.len().into()),
+            Value::Map(map) => Ok(map.len().into()),
+            other => Err(type_error("len", 0, "string, list or map", other)),
+        }),
+        NativeFunction::new("substr", 3, |args| {
+            let text = string("substr", args, 0)?;
+            let start = index("substr", args, 1)?;
+            let length = index("substr", args, 2)?;
+            let count = text.chars().count();
+            if start > count {
+                return Err(format!(
+                    "substr() start {start} is past the end of a string of length {count}."
+                ));
+            }
+            Ok(text
+                .chars()
+                .skip(start)
+                .take(length)
+                .collect::<String>()
+                .into())
+        }),
+        NativeFunction::new("upper", 1, |args| {
+            Ok(string("upper", args, 0)?.to_uppercase().into())
+        }),
+        NativeFunction::new("lower", 1, |args| {
+            Ok(string("lower", args, 0)?.to_lowercase().into())
+        }),
+        NativeFunction::new("index_of", 2, |args| {
+            let text = string("index_of", args, 0)?;
+            let needle = string("index_of", args, 1)?;
+            Ok(match text.find(needle) {
+                Some(offset) => text[..offset].chars().count().into(),
+                None => Value::Number(-1.0),
+            })
+        }),
+        NativeFunction::new("trim", 1, |args| Ok(string("trim", args, 0)?.trim().into())),
+        // An empty separator splits the text into characters.
+        NativeFunction::new("split", 2, |args| {
+            let text = string("split", args, 0)?;
+            let separator = string("split", args, 1)?;
+            let parts: Vec<Value> = if separator.is_empty() {
+                text.chars().map(|c| c.to_string().into()).collect()
+            } else {
+                text.split(separator).map(Value::from).collect()
+            };
+            Ok(parts.into())
+        }),
+        NativeFunction::new("push", 2, |args| {
+            list("push", args, 0)?.elements_mut().push(args[1].clone());
+            Ok(Value::None)
+        }),
+        NativeFunction::new("pop", 1, |args| {
+            list("pop", args, 0)?
+                .elements_mut()
+                .pop()
+                .ok_or_else(|| "Can't pop from an empty list.".to_string())
+        }),
+        // Unlike indexing, inserting at the length adds to the end.
+        NativeFunction::new("insert", 3, |args| {
+            let list = list("insert", args, 0)?;
+            let index = number("insert", args, 1)?;
+            let position = if index == list.len() as f64 {
+                list.len()
+            } else {
+                list.position(index)?
+            };
+            list.elements_mut().insert(position, args[2].clone());
+            Ok(Value::None)
+        }),
+        NativeFunction::new("remove", 2, |args| match &args[0] {
+            Value::List(list) => {
+                let position = list.position(number("remove", args, 1)?)?;
+                Ok(list.elements_mut().remove(position))
+            }
+            Value::Map(map) => map
+                .remove(&args[1])?
+                .ok_or_else(|| format!("Key {} is not in the map.", args[1].repr())),
+            other => Err(type_error("remove", 0, "list or map", other)),
+        }),
+        NativeFunction::new("has", 2, |args| {
+            Ok(map("has", args, 0)?.get(&args[1])?.is_some().into())
+        }),
+        NativeFunction::new("keys", 1, |args| {
+            let entries = map("keys", args, 0)?.entries();
+            Ok(entries
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+                .into())
+        }),
+        NativeFunction::new("values", 1, |args| {
+            let entries = map("values", args, 0)?.entries();
+            Ok(entries
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+                .into())
+        }),
+        // Like Python, the bounds may be negative, and are clamped to the
+        // list rather than being errors.
+        NativeFunction::new("slice", 3, |args| {
+            let list = list("slice", args, 0)?;
+            let len = list.len() as f64;
+            let bound = |position: usize| -> Result<usize, String> {
+                let n = number("slice", args, position)?;
+                if n.fract() != 0.0 {
+                    return Err(format!(
+                        "Argument {} to slice() must be a whole number, not {}.",
+                        position + 1,
+                        Value::Number(n)
+                    ));
+                }
+                let n = if n < 0.0 { n + len } else { n };
+                Ok(n.clamp(0.0, len) as usize)
+            };
+            let (start, end) = (bound(1)?, bound(2)?);
+            let elements = list.elements();
+            Ok(elements[start..end.max(start)].to_vec().into())
+        }),
+        // Text that isn't a number gives nil, so that programs can check.
+        NativeFunction::new("num", 1, |args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(*n)),
+            Value::String(text) => Ok(text
+                .trim()
+                .parse::<f64>()
+                .map_or(Value::None, Value::Number)),
+            other => Err(type_error("num", 0, "number or string", other)),
+        }),
+        NativeFunction::new("str", 1, |args| Ok(args[0].to_string().into())),
+        NativeFunction::new("type", 1, |args| Ok(args[0].type_name().into())),
+    ]
+}
+
+/// A function of one number.
+fn math(name: &'static str, function: fn(f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, 1, move |args| {
+        Ok(function(number(name, args, 0)?).into())
+    })
+}
+
+fn number(function: &str, args: &[Value], position: usize) -> Result<f64, String> {
+    match &args[position] {
+        Value::Number(n) => Ok(*n),
+        other => Err(type_error(function, position, "number", other)),
+    }
+}
+
+fn list<'a>(function: &str, args: &'a [Value], position: usize) -> Result<&'a List, String> {
+    match &args[position] {
+        Value::List(list) => Ok(list),
+        other => Err(type_error(function, position, "list", other)),
+    }
+}
+
+fn map<'a>(function: &str, args: &'a [Value], position: usize) -> Result<&'a Map, String> {
+    match &args[position] {
+        Value::Map(map) => Ok(map),
+        other => Err(type_error(function, position, "map", other)),
+    }
+}
+
+fn string<'a>(function: &str, args: &'a [Value], position: usize) -> Result<&'a str, String> {
+    match &args[position] {
+        Value::String(text) => Ok(text),
+        other => Err(type_error(function, position, "string", other)),
+    }
+}
+
+/// A number that can index into a string: a whole number that isn't negative.
+fn index(function: &str, args: &[Value], position: usize) -> Result<usize, String> {
+    let n = number(function, args, position)?;
+    if n < 0.0 || n.fract() != 0.0 || n > u32::MAX as f64 {
+        return Err(format!(
+            "Argument {} to {function}() must be a whole number that isn't negative, not {}.",
+            position + 1,
+            Value::Number(n)
+        ));
+    }
+    Ok(n as usize)
+}
+
+fn type_error(function: &str, position: usize, expected: &str, found: &Value) -> String {
+    format!(
+        "Argument {} to {function}() must be a {expected}, not a {}.",
+        position + 1,
+        found.type_name()
+    )
+}
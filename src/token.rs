@@ -1,22 +1,128 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    rc::Rc,
+};
 
-use crate::token_type::TokenType;
+use thiserror::Error;
 
+use crate::{
+    callable::{LoxFunction, NativeFunction},
+    class::{LoxClass, LoxInstance},
+    list::List,
+    map::Map,
+    token_type::TokenType,
+    vm::{BoundMethod, Closure},
+};
+
+/// A Lox value. Functions and classes are shared handles: the tree-walking
+/// interpreter creates `Function`s, while the bytecode VM creates `Closure`s
+/// and `BoundMethod`s.
+///
+/// ```
+/// use lox::{Lox, Value};
+///
+/// let mut lox = Lox::new();
+/// lox.run("class Point { init(x) { this.x = x; } } fun origin() { return Point(0); }")
+///     .unwrap();
+/// let Value::Instance(point) = lox.call("origin", vec![]).unwrap() else {
+///     panic!("expected an instance");
+/// };
+/// assert_eq!(point.class().name(), "Point");
+/// assert_eq!(point.field("x"), Some(Value::Number(0.0)));
+/// ```
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Number(f64),
     String(String),
     Bool(bool),
     None,
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<LoxInstance>),
+    List(List),
+    Map(Map),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
 }
 
+/// The form of a value that `print` shows and string conversion produces,
+/// which matches jlox.
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Number(num) => write!(f, "{num}"),
+            Self::Number(num) => f.write_str(&format_number(*num)),
             Self::Bool(b) => write!(f, "{b}"),
-            Self::String(value) => write!(f, "\"{value}\""),
+            Self::String(value) => f.write_str(value),
             Self::None => f.write_str("nil"),
+            Self::Function(function) => write!(f, "<fn {}>", function.name()),
+            Self::Native(_) => f.write_str("<native fn>"),
+            Self::Class(class) => f.write_str(&class.name),
+            Self::Instance(instance) => write!(f, "{} instance", instance.class.name),
+            Self::List(list) => write!(f, "{list}"),
+            Self::Map(map) => write!(f, "{map}"),
+            Self::Closure(closure) => write!(f, "{}", closure.function),
+            Self::BoundMethod(bound) => write!(f, "{}", bound.method.function),
+        }
+    }
+}
+
+/// Format a number as jlox does, which is Java's `Double.toString` without a
+/// trailing `.0`: `3`, `0.5`, `1.0E7`, `1.5E-5`, `NaN` and `Infinity`.
+fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        return "NaN".into();
+    }
+    if number.is_infinite() {
+        return if number > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }
+        .into();
+    }
+    let magnitude = number.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        // Rust already leaves off the `.0` of whole numbers.
+        return number.to_string();
+    }
+    // Outside that range Java uses scientific notation, with at least one
+    // digit after the point.
+    let scientific = format!("{number:e}");
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("Scientific notation has an exponent.");
+    if mantissa.contains('.') {
+        format!("{mantissa}E{exponent}")
+    } else {
+        format!("{mantissa}.0E{exponent}")
+    }
+}
+
+impl Value {
+    /// The form used when printing syntax trees, in which strings are quoted
+    /// so that they can be told apart from other literals.
+    pub fn repr(&self) -> String {
+        match self {
+            Self::String(value) => format!("\"{value}\""),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Bool(_) => "boolean",
+            Self::None => "nil",
+            Self::Function(_) | Self::Native(_) | Self::Closure(_) | Self::BoundMethod(_) => {
+                "function"
+            }
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
         }
     }
 }
@@ -45,6 +151,54 @@ impl From<isize> for Value {
     }
 }
 
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Number(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::None
+    }
+}
+
+impl From<NativeFunction> for Value {
+    fn from(value: NativeFunction) -> Self {
+        Value::Native(Rc::new(value))
+    }
+}
+
+impl From<List> for Value {
+    fn from(value: List) -> Self {
+        Value::List(value)
+    }
+}
+
+impl From<Map> for Value {
+    fn from(value: Map) -> Self {
+        Value::Map(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::List(List::new(value))
+    }
+}
+
 impl From<f64> for Value {
     fn from(value: f64) -> Self {
         Value::Number(value)
@@ -69,21 +223,286 @@ impl From<Option<f64>> for Value {
     }
 }
 
+/// The error returned when converting a `Value` into a Rust type it doesn't
+/// hold.
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("Expected a {expected} but got a {found}.")]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl ConversionError {
+    fn new(expected: &'static str, value: &Value) -> Self {
+        Self {
+            expected,
+            found: value.type_name(),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(number) => Ok(number),
+            _ => Err(ConversionError::new("number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(ConversionError::new("boolean", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(string) => Ok(string),
+            _ => Err(ConversionError::new("string", &value)),
+        }
+    }
+}
+
+/// The whole number `value` holds, if it is one from `min` up to but not
+/// including `end`.
+fn whole_number(
+    value: &Value,
+    expected: &'static str,
+    min: f64,
+    end: f64,
+) -> Result<f64, ConversionError> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 && *n >= min && *n < end => Ok(*n),
+        _ => Err(ConversionError::new(expected, value)),
+    }
+}
+
+// The bounds are computed in f64, where `MAX as f64 + 1.0` is the first
+// number past `MAX` even when `MAX` itself rounds up.
+
+impl TryFrom<Value> for i32 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let (min, end) = (i32::MIN as f64, i32::MAX as f64 + 1.0);
+        Ok(whole_number(&value, "whole number that fits in an i32", min, end)? as i32)
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let (min, end) = (i64::MIN as f64, i64::MAX as f64 + 1.0);
+        Ok(whole_number(&value, "whole number that fits in an i64", min, end)? as i64)
+    }
+}
+
+impl TryFrom<Value> for isize {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let (min, end) = (isize::MIN as f64, isize::MAX as f64 + 1.0);
+        Ok(whole_number(&value, "whole number that fits in an isize", min, end)? as isize)
+    }
+}
+
+impl TryFrom<Value> for usize {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let end = usize::MAX as f64 + 1.0;
+        Ok(whole_number(&value, "whole number that fits in a usize", 0.0, end)? as usize)
+    }
+}
+
+/// `nil` converts to `None`, and anything else to `Some` if it is a string.
+impl TryFrom<Value> for Option<String> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::None => Ok(None),
+            Value::String(string) => Ok(Some(string)),
+            _ => Err(ConversionError::new("string or nil", &value)),
+        }
+    }
+}
+
+/// `nil` converts to `None`, and anything else to `Some` if it is a number.
+impl TryFrom<Value> for Option<f64> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::None => Ok(None),
+            Value::Number(number) => Ok(Some(number)),
+            _ => Err(ConversionError::new("number or nil", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for () {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::None => Ok(()),
+            _ => Err(ConversionError::new("nil", &value)),
+        }
+    }
+}
+
+/// A region of source code. `offset` and `len` are in bytes; `line` and
+/// `column` locate the start of the region and are both 1-based, with the
+/// column counted in characters.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(offset: usize, len: usize, line: usize, column: usize) -> Self {
+        Self {
+            offset,
+            len,
+            line,
+            column,
+        }
+    }
+
+    /// The byte offset just past the end of the span.
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    /// A span running from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            len: self.end().max(other.end()) - self.offset,
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Value,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Value, line: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: String, literal: Value, span: Span) -> Self {
         Self {
             token_type,
             lexeme,
             literal,
-            line,
+            span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_to() {
+        let start = Span::new(4, 3, 1, 5);
+        let end = Span::new(10, 2, 2, 1);
+        assert_eq!(start.to(end), Span::new(4, 8, 1, 5));
+        assert_eq!(start.to(start), start);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(f64::try_from(Value::from(3)), Ok(3.0));
+        assert_eq!(String::try_from(Value::from("a")), Ok("a".to_string()));
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert_eq!(<()>::try_from(Value::from(())), Ok(()));
+        let error = f64::try_from(Value::from("a")).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a number but got a string.");
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        assert_eq!(i32::try_from(Value::from(-3)), Ok(-3));
+        assert_eq!(i64::try_from(Value::from(-0.0)), Ok(0));
+        assert_eq!(isize::try_from(Value::from(7)), Ok(7));
+        assert_eq!(usize::try_from(Value::from(7)), Ok(7));
+        assert_eq!(i32::try_from(Value::from(i32::MIN)), Ok(i32::MIN));
+        assert_eq!(i32::try_from(Value::from(i32::MAX)), Ok(i32::MAX));
+
+        assert!(i32::try_from(Value::from(i32::MAX as f64 + 1.0)).is_err());
+        assert!(i64::try_from(Value::from(i64::MAX as f64)).is_err());
+        assert!(i64::try_from(Value::from(i64::MIN as f64)).is_ok());
+        assert!(usize::try_from(Value::from(-1)).is_err());
+        assert!(usize::try_from(Value::from(usize::MAX as f64)).is_err());
+        assert!(i64::try_from(Value::from(f64::NAN)).is_err());
+        assert!(i64::try_from(Value::from(f64::INFINITY)).is_err());
+        let error = i32::try_from(Value::from(1.5)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected a whole number that fits in an i32 but got a number."
+        );
+        assert!(usize::try_from(Value::from("1")).is_err());
+    }
+
+    #[test]
+    fn test_option_conversions() {
+        assert_eq!(Option::<f64>::try_from(Value::None), Ok(None));
+        assert_eq!(Option::<f64>::try_from(Value::from(2.5)), Ok(Some(2.5)));
+        assert_eq!(Option::<String>::try_from(Value::None), Ok(None));
+        assert_eq!(
+            Option::<String>::try_from(Value::from("a")),
+            Ok(Some("a".to_string()))
+        );
+        let error = Option::<f64>::try_from(Value::from(true)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected a number or nil but got a boolean."
+        );
+    }
+
+    #[test]
+    fn test_display_matches_jlox() {
+        let cases = [
+            (Value::from(3.0), "3"),
+            (Value::from(-0.0), "-0"),
+            (Value::from(2.5), "2.5"),
+            (Value::from(0.001), "0.001"),
+            (Value::from(1234567.0), "1234567"),
+            (Value::from(1e7), "1.0E7"),
+            (Value::from(123456789.0), "1.23456789E8"),
+            (Value::from(-0.0001), "-1.0E-4"),
+            (Value::from(1.5e-10), "1.5E-10"),
+            (Value::from(f64::NAN), "NaN"),
+            (Value::from(f64::INFINITY), "Infinity"),
+            (Value::from(f64::NEG_INFINITY), "-Infinity"),
+            (Value::from("hi"), "hi"),
+            (Value::from(true), "true"),
+            (Value::None, "nil"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(value.to_string(), expected, "{value:?}");
         }
+        assert_eq!(Value::from("hi").repr(), "\"hi\"");
+        assert_eq!(Value::from(3.0).repr(), "3");
     }
 }
@@ -0,0 +1,873 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    chunk::{Constant, Function, OpCode},
+    class::{LoxClass, LoxInstance},
+    diagnostic::ErrorCode,
+    disassembler::disassemble_instruction,
+    interpreter::{RuntimeError, is_truthy},
+    list::{get_index, set_index},
+    map::Map,
+    output::Output,
+    reporter::Reporter,
+    stdlib::natives,
+    token::{Span, Value},
+};
+
+/// The deepest the call stack can grow before reporting a stack overflow.
+/// The script itself takes the first frame.
+pub(crate) const FRAMES_MAX: usize = 1024;
+
+/// A compiled function together with the variables it has captured.
+pub struct Closure {
+    pub(crate) function: Rc<Function>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.function.arity
+    }
+}
+
+/// A captured variable. It stays open, pointing at a stack slot, until that
+/// slot goes out of scope, when the value is moved into the upvalue itself.
+#[derive(Debug)]
+enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A method closure paired with the instance it was accessed on.
+pub struct BoundMethod {
+    pub(crate) receiver: Value,
+    pub(crate) method: Rc<Closure>,
+}
+
+impl BoundMethod {
+    pub fn receiver(&self) -> &Value {
+        &self.receiver
+    }
+
+    pub fn method(&self) -> &Rc<Closure> {
+        &self.method
+    }
+}
+
+// Closures and bound methods compare by identity, and may (indirectly)
+// contain themselves, so neither trait can be derived.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl PartialEq for BoundMethod {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method.function)
+    }
+}
+
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The index of the frame's first stack slot, which holds the callee.
+    slots: usize,
+}
+
+/// A stack-based virtual machine that runs compiled bytecode. Globals are
+/// kept between calls to `interpret`.
+#[derive(Debug)]
+pub struct Vm {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    globals: HashMap<Rc<str>, Value>,
+    /// Upvalues still pointing at the stack, so closures created in the same
+    /// scope share them.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Print the stack and each instruction to stderr as it executes.
+    trace: bool,
+    output: Output,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        let globals = natives()
+            .into_iter()
+            .map(|native| (native.name().into(), native.into()))
+            .collect();
+        Self {
+            frames: vec![],
+            stack: vec![],
+            globals,
+            open_upvalues: vec![],
+            trace: false,
+            output: Output::default(),
+        }
+    }
+}
+
+impl Vm {
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Send the output of `print` statements to `output`.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn interpret(&mut self, reporter: &mut dyn Reporter, function: Rc<Function>) {
+        if let Err(error) = self.run_script(function) {
+            self.reset();
+            reporter.runtime_error((&error).into());
+        }
+    }
+
+    /// Define a global variable, replacing any existing one of the same
+    /// name.
+    pub fn define_global(&mut self, name: impl Into<String>, value: Value) {
+        self.globals.insert(name.into().into(), value);
+    }
+
+    /// Every global variable and its value, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    /// Call the function or class held by the global `name`, running it to
+    /// completion. Errors have no location in the source unless they are
+    /// raised inside the function.
+    pub fn call_global(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let Some(callee) = self.globals.get(name).cloned() else {
+            return Err(RuntimeError::at(
+                ErrorCode::UndefinedVariable,
+                Span::default(),
+                format!("Undefined variable '{name}'."),
+            ));
+        };
+        let arg_count = arguments.len();
+        self.push(callee.clone());
+        self.stack.extend(arguments);
+        let result = self.call_value(callee, arg_count).and_then(|()| {
+            // Natives, and classes without an initializer, have already
+            // left their result on the stack.
+            if self.frames.is_empty() {
+                Ok(self.pop())
+            } else {
+                self.run()
+            }
+        });
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    fn run_script(&mut self, function: Rc<Function>) -> Result<(), RuntimeError> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: vec![],
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.call(closure, 0)?;
+        self.run().map(|_| ())
+    }
+
+    /// Run until the outermost frame returns, giving its result.
+    fn run(&mut self) -> Result<Value, RuntimeError> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            let byte = self.read_byte();
+            let Ok(op) = OpCode::try_from(byte) else {
+                return Err(self.error(ErrorCode::Internal, format!("Unknown opcode {byte}.")));
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Number(number) => Value::Number(number),
+                        Constant::String(string) => Value::String(string.to_string()),
+                        Constant::Function(_) => {
+                            return Err(self.error(
+                                ErrorCode::Internal,
+                                "Functions must be loaded as closures.",
+                            ));
+                        }
+                    };
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(Value::None),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    let Some(value) = self.globals.get(&name) else {
+                        return Err(self.error(
+                            ErrorCode::UndefinedVariable,
+                            format!("Undefined variable '{name}'."),
+                        ));
+                    };
+                    self.push(value.clone());
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0).clone();
+                    let Some(slot) = self.globals.get_mut(&name) else {
+                        return Err(self.error(
+                            ErrorCode::UndefinedVariable,
+                            format!("Undefined variable '{name}'."),
+                        ));
+                    };
+                    *slot = value;
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let Value::Instance(instance) = self.peek(0).clone() else {
+                        return Err(
+                            self.error(ErrorCode::TypeMismatch, "Only instances have properties.")
+                        );
+                    };
+                    match instance.field(&name) {
+                        Some(value) => {
+                            self.pop();
+                            self.push(value);
+                        }
+                        None => self.bind_method(&instance.class, &name)?,
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let Value::Instance(instance) = self.peek(1) else {
+                        return Err(
+                            self.error(ErrorCode::TypeMismatch, "Only instances have fields.")
+                        );
+                    };
+                    instance.set(&*name, self.peek(0).clone());
+                    let value = self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let superclass = self.pop_class()?;
+                    self.bind_method(&superclass, &name)?;
+                }
+                OpCode::BuildList => {
+                    let count = self.read_byte() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.push(elements.into());
+                }
+                OpCode::ExtendList => {
+                    let count = self.read_byte() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    let Value::List(list) = self.peek(0) else {
+                        return Err(self.error(ErrorCode::Internal, "Only lists can be extended."));
+                    };
+                    list.elements_mut().extend(elements);
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_byte() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                    let map = Map::new();
+                    for entry in entries.chunks_exact(2) {
+                        map.insert(entry[0].clone(), entry[1].clone())
+                            .map_err(|message| self.error(ErrorCode::TypeMismatch, message))?;
+                    }
+                    self.push(map.into());
+                }
+                OpCode::ExtendMap => {
+                    let count = self.read_byte() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                    let Value::Map(map) = self.peek(0) else {
+                        return Err(self.error(ErrorCode::Internal, "Only maps can be extended."));
+                    };
+                    for entry in entries.chunks_exact(2) {
+                        map.insert(entry[0].clone(), entry[1].clone())
+                            .map_err(|message| self.error(ErrorCode::TypeMismatch, message))?;
+                    }
+                }
+                OpCode::GetIndex => {
+                    let (object, index) = self.pop_pair();
+                    let element = get_index(&object, &index, self.span())?;
+                    self.push(element);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let (object, index) = self.pop_pair();
+                    set_index(&object, &index, value.clone(), self.span())?;
+                    self.push(value);
+                }
+                OpCode::Equal => {
+                    let (a, b) = self.pop_pair();
+                    self.push(Value::Bool(a == b));
+                }
+                OpCode::NotEqual => {
+                    let (a, b) = self.pop_pair();
+                    self.push(Value::Bool(a != b));
+                }
+                OpCode::Greater => self.binary_op(|a, b| Value::Bool(a > b))?,
+                OpCode::GreaterEqual => self.binary_op(|a, b| Value::Bool(a >= b))?,
+                OpCode::Less => self.binary_op(|a, b| Value::Bool(a < b))?,
+                OpCode::LessEqual => self.binary_op(|a, b| Value::Bool(a <= b))?,
+                OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::String(_), Value::String(_)) => {
+                        let (Value::String(mut a), Value::String(b)) = self.pop_pair() else {
+                            unreachable!("Both operands were just checked to be strings.");
+                        };
+                        a.push_str(&b);
+                        self.push(Value::String(a));
+                    }
+                    (Value::Number(_), Value::Number(_)) => {
+                        self.binary_op(|a, b| Value::Number(a + b))?
+                    }
+                    _ => {
+                        return Err(self.error(
+                            ErrorCode::TypeMismatch,
+                            "Operands must be two numbers or two strings.",
+                        ));
+                    }
+                },
+                OpCode::Subtract => self.binary_op(|a, b| Value::Number(a - b))?,
+                OpCode::Multiply => self.binary_op(|a, b| Value::Number(a * b))?,
+                OpCode::Divide => self.binary_op(|a, b| Value::Number(a / b))?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::Bool(!is_truthy(&value)));
+                }
+                OpCode::Negate => {
+                    let Value::Number(number) = self.peek(0) else {
+                        return Err(
+                            self.error(ErrorCode::TypeMismatch, "Operand must be a number.")
+                        );
+                    };
+                    let negated = Value::Number(-number);
+                    self.pop();
+                    self.push(negated);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    self.output.print(&value, self.span())?;
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !is_truthy(self.peek(0)) {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count).clone(), arg_count)?;
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(&name, arg_count)?;
+                }
+                OpCode::SuperInvoke => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.pop_class()?;
+                    self.invoke_from_class(&superclass, &name, arg_count)?;
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        return Err(self.error(
+                            ErrorCode::Internal,
+                            "Closures must be created from functions.",
+                        ));
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.frame().closure.upvalues[index].clone()
+                        });
+                    }
+                    self.push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("A frame is always running.");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = LoxClass::new(&*name, None, HashMap::new());
+                    self.push(Value::Class(Rc::new(class)));
+                }
+                OpCode::Inherit => {
+                    let Value::Class(superclass) = self.peek(1) else {
+                        return Err(
+                            self.error(ErrorCode::TypeMismatch, "Superclass must be a class.")
+                        );
+                    };
+                    if let Value::Class(subclass) = self.peek(0) {
+                        subclass.inherit(superclass);
+                    }
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = self.pop();
+                    if let Value::Class(class) = self.peek(0) {
+                        class.add_method(&*name, method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), RuntimeError> {
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), arg_count)
+            }
+            Value::Native(native) => {
+                if arg_count != native.arity() {
+                    return Err(self.error(
+                        ErrorCode::ArityMismatch,
+                        format!(
+                            "Expected {} arguments but got {}.",
+                            native.arity(),
+                            arg_count
+                        ),
+                    ));
+                }
+                let arguments = self.stack.split_off(callee_slot + 1);
+                let result = native
+                    .call(&arguments)
+                    .map_err(|message| self.error(ErrorCode::NativeError, message))?;
+                self.stack[callee_slot] = result;
+                Ok(())
+            }
+            Value::Class(class) => {
+                let instance = LoxInstance::new(class.clone());
+                self.stack[callee_slot] = Value::Instance(Rc::new(instance));
+                match class.find_method("init") {
+                    Some(Value::Closure(initializer)) => self.call(initializer, arg_count),
+                    _ if arg_count != 0 => Err(self.error(
+                        ErrorCode::ArityMismatch,
+                        format!("Expected 0 arguments but got {arg_count}."),
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            _ => Err(self.error(
+                ErrorCode::NotCallable,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<(), RuntimeError> {
+        if arg_count != closure.function.arity {
+            return Err(self.error(
+                ErrorCode::ArityMismatch,
+                format!(
+                    "Expected {} arguments but got {}.",
+                    closure.function.arity, arg_count
+                ),
+            ));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error(ErrorCode::StackOverflow, "Stack overflow."));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    fn invoke(&mut self, name: &str, arg_count: usize) -> Result<(), RuntimeError> {
+        let Value::Instance(instance) = self.peek(arg_count).clone() else {
+            return Err(self.error(ErrorCode::TypeMismatch, "Only instances have properties."));
+        };
+        // A field holding a function shadows any method with the same name.
+        if let Some(value) = instance.field(name) {
+            let callee_slot = self.stack.len() - arg_count - 1;
+            self.stack[callee_slot] = value.clone();
+            return self.call_value(value, arg_count);
+        }
+        self.invoke_from_class(&instance.class, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: &LoxClass,
+        name: &str,
+        arg_count: usize,
+    ) -> Result<(), RuntimeError> {
+        match class.find_method(name) {
+            Some(Value::Closure(method)) => self.call(method, arg_count),
+            _ => Err(self.error(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{name}'."),
+            )),
+        }
+    }
+
+    /// Replace the instance on top of the stack with its method `name`.
+    fn bind_method(&mut self, class: &LoxClass, name: &str) -> Result<(), RuntimeError> {
+        let Some(Value::Closure(method)) = class.find_method(name) else {
+            return Err(self.error(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{name}'."),
+            ));
+        };
+        let receiver = self.pop();
+        self.push(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver,
+            method,
+        })));
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Close every open upvalue pointing at `first_slot` or above.
+    fn close_upvalues(&mut self, first_slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= first_slot => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn binary_op(&mut self, op: impl Fn(f64, f64) -> Value) -> Result<(), RuntimeError> {
+        let (Value::Number(a), Value::Number(b)) = (self.peek(1), self.peek(0)) else {
+            return Err(self.error(ErrorCode::TypeMismatch, "Operands must be numbers."));
+        };
+        let result = op(*a, *b);
+        self.pop_pair();
+        self.push(result);
+        Ok(())
+    }
+
+    fn trace_instruction(&self) {
+        let stack: String = self
+            .stack
+            .iter()
+            .map(|value| format!("[ {value} ]"))
+            .collect();
+        let frame = self.frame();
+        let (instruction, _) = disassemble_instruction(&frame.closure.function.chunk, frame.ip);
+        eprintln!("          {stack}");
+        eprintln!("{instruction}");
+    }
+
+    // -------------------------------------------------------------------------
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("A frame is always running.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("A frame is always running.")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index = self.read_u16() as usize;
+        match &self.frame().closure.function.chunk.constants[index] {
+            Constant::Number(number) => Constant::Number(*number),
+            Constant::String(string) => Constant::String(string.clone()),
+            Constant::Function(function) => Constant::Function(function.clone()),
+        }
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Constant::String(string) => string,
+            _ => "".into(),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("The compiler keeps the stack balanced.")
+    }
+
+    /// Pop two operands, returning them in the order they were pushed.
+    fn pop_pair(&mut self) -> (Value, Value) {
+        let b = self.pop();
+        let a = self.pop();
+        (a, b)
+    }
+
+    fn pop_class(&mut self) -> Result<Rc<LoxClass>, RuntimeError> {
+        match self.pop() {
+            Value::Class(class) => Ok(class),
+            _ => Err(self.error(ErrorCode::TypeMismatch, "Superclass must be a class.")),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The source of the instruction being executed. A call from Rust may
+    /// fail before any instruction has run, so has no source.
+    fn span(&self) -> Span {
+        self.frames.last().map_or(Span::default(), |frame| {
+            frame.closure.function.chunk.spans[frame.ip - 1]
+        })
+    }
+
+    /// Create an error at the source of the instruction being executed.
+    fn error(&self, code: ErrorCode, message: impl Into<String>) -> RuntimeError {
+        RuntimeError::at(code, self.span(), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compiler::compile, parser::parse, reporter::Collector, resolver::resolve,
+        scanner::scan_tokens,
+    };
+
+    fn run(vm: &mut Vm, source: &str) -> Collector {
+        let mut reporter = Collector::default();
+        let (tokens, _) = scan_tokens(source);
+        let (statements, _) = parse(tokens);
+        resolve(&mut reporter, &statements);
+        let function = compile(&mut reporter, &statements).unwrap();
+        vm.interpret(&mut reporter, function);
+        reporter
+    }
+
+    fn get(vm: &Vm, name: &str) -> Value {
+        vm.globals
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| panic!("Undefined global {name}"))
+    }
+
+    #[test]
+    fn test_globals_persist_between_runs() {
+        let mut vm = Vm::default();
+        run(&mut vm, "var a = 1;");
+        run(&mut vm, "a = a + 2; var s = \"a\" + \"b\";");
+        assert_eq!(get(&vm, "a"), Value::Number(3.0));
+        assert_eq!(get(&vm, "s"), Value::from("ab"));
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut vm = Vm::default();
+        run(
+            &mut vm,
+            "fun make_counter() {
+                var i = 0;
+                fun count() { i = i + 1; return i; }
+                return count;
+            }
+            var counter = make_counter();
+            counter();
+            var a = counter();
+            var b = make_counter()();
+            var shared;
+            {
+                var x = 1;
+                fun get() { return x; }
+                fun set() { x = 2; }
+                set();
+                shared = get;
+            }
+            var c = shared();",
+        );
+        assert_eq!(get(&vm, "a"), Value::Number(2.0));
+        assert_eq!(get(&vm, "b"), Value::Number(1.0));
+        assert_eq!(get(&vm, "c"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_classes_and_inheritance() {
+        let mut vm = Vm::default();
+        run(
+            &mut vm,
+            "class A {
+                init(name) { this.name = name; }
+                greet() { return \"A \" + this.name; }
+            }
+            class B < A {
+                greet() { return \"B then \" + super.greet(); }
+                method() { return super.greet; }
+            }
+            var b = B(\"b\");
+            var greeting = b.greet();
+            var bound = b.method()();
+            b.field = b.greet;
+            var field = b.field();",
+        );
+        assert_eq!(get(&vm, "greeting"), Value::from("B then A b"));
+        assert_eq!(get(&vm, "bound"), Value::from("A b"));
+        assert_eq!(get(&vm, "field"), Value::from("B then A b"));
+    }
+
+    #[test]
+    fn test_runtime_error_resets_stack() {
+        let mut vm = Vm::default();
+        let reporter = run(&mut vm, "var a = 1;\nfun f() { return -\"x\"; }\nf();");
+        assert!(reporter.had_runtime_error());
+        assert!(vm.stack.is_empty());
+        assert!(vm.frames.is_empty());
+        run(&mut vm, "a = 2;");
+        assert_eq!(get(&vm, "a"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_comparisons_and_logic() {
+        let mut vm = Vm::default();
+        run(
+            &mut vm,
+            "var total = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                if (i == 3 or i == 5) total = total + 100;
+                else if (!(i > 7)) total = total + i;
+            }
+            var nan = 0 / 0;
+            var cmp = nan >= nan;
+            var ne = nan != nan;
+            var logic = nil or \"default\";
+            var short = false and undefined;",
+        );
+        assert_eq!(get(&vm, "total"), Value::Number(220.0));
+        assert_eq!(get(&vm, "cmp"), Value::Bool(false));
+        assert_eq!(get(&vm, "ne"), Value::Bool(true));
+        assert_eq!(get(&vm, "logic"), Value::from("default"));
+        assert_eq!(get(&vm, "short"), Value::Bool(false));
+    }
+}
@@ -0,0 +1,22 @@
+use std::{fs, path::Path};
+
+use lox::{Backend, conformance};
+
+#[test]
+fn test_conformance() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let backends = [Backend::TreeWalk, Backend::Bytecode];
+    let summary = conformance::run_dir(&dir, &backends).unwrap();
+    assert!(summary.succeeded(), "{summary}");
+
+    // Every test ran on every backend, so none were missed.
+    let files = fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            let path = entry.as_ref().unwrap().path();
+            path.extension().is_some_and(|extension| extension == "lox")
+        })
+        .count();
+    assert!(files > 0);
+    assert_eq!(summary.passed, (files - summary.skipped) * backends.len());
+}